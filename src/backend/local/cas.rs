@@ -0,0 +1,272 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use snafu::ResultExt;
+use tracing::debug;
+
+use super::{
+    checksum::{hash_file, Digests},
+    file_len,
+    fs::{create_dirs, set_owner},
+    temp_path, Error, Local, ObjectSnafu,
+};
+use crate::path::RelativePath;
+
+/// The folder below the folder of the backend the objects are stored in.
+pub(super) const OBJECTS_DIR: &str = ".objects";
+
+/// Starts the content of pointer files, followed by the hex SHA-256.
+const POINTER_PREFIX: &str = "sha256:";
+
+/// Larger files aren't read as pointers.
+const MAX_POINTER_SIZE: u64 = 128;
+
+/// How stored objects show up at the upload paths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentAddressing {
+    /// A hardlink to the object. Identical uploads share the metadata of the
+    /// first one.
+    Hardlinks,
+    /// A small file containing `sha256:<hash>`, see [`Local::resolve`].
+    Pointers,
+}
+
+/// The result of [`Local::collect_garbage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GarbageCollection {
+    /// The number of deleted objects.
+    pub removed: u64,
+    /// The bytes they took.
+    pub freed: u64,
+}
+
+impl Local {
+    /// The object with the hex SHA-256 `hash`, e.g. `.objects/ab/cdef…`.
+    fn object_path(&self, hash: &str) -> PathBuf {
+        let (fan_out, name) = hash.split_at(2);
+        self.folder.join(OBJECTS_DIR).join(fan_out).join(name)
+    }
+
+    /// Move a written temp file into the object store, unless the object
+    /// exists already, and put a hardlink or pointer to it at `path`.
+    pub(super) async fn store_object(
+        &self,
+        mode: ContentAddressing,
+        result: Result<(), Error>,
+        written: &Path,
+        path: &Path,
+        digests: Option<&Digests>,
+    ) -> Result<(), Error> {
+        let msg = || path.to_string_lossy().to_string();
+        let hash = match (result, digests) {
+            (Ok(()), Some(digests)) => Ok(digests.sha256().to_string()),
+            (Ok(()), None) => hash_file(written, None)
+                .await
+                .map(|digests| digests.sha256().to_string())
+                .with_context(|_| ObjectSnafu { msg: msg() }),
+            (Err(e), _) => Err(e),
+        };
+        // Keeps garbage collection from deleting the object before it is linked
+        let _guard = self.gc_lock.read().await;
+        let object = match hash {
+            Ok(hash) => self
+                .move_to_object(written, &hash)
+                .await
+                .map(|object| (hash, object)),
+            Err(e) => Err(e),
+        };
+        let (hash, (object, created)) = match object {
+            Ok(object) => object,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(written).await;
+                return Err(e);
+            }
+        };
+        // Pointers are counted when they replace the file at `path`
+        if created {
+            self.account(file_len(&object).await, 0);
+        }
+
+        let link_path = temp_path(path);
+        let result = match mode {
+            ContentAddressing::Hardlinks => tokio::fs::hard_link(&object, &link_path).await,
+            ContentAddressing::Pointers => {
+                let pointer = format!("{}{}\n", POINTER_PREFIX, hash);
+                async {
+                    tokio::fs::write(&link_path, pointer).await?;
+                    set_owner(&link_path, self.options.group, self.options.file_mode).await
+                }
+                .await
+            }
+        };
+        let result = result.with_context(|_| ObjectSnafu { msg: msg() });
+        self.replace(result, &link_path, path).await
+    }
+
+    /// Rename the temp file to the object of `hash`, or drop it if the object
+    /// is stored already. Returns the object and whether it was created.
+    async fn move_to_object(&self, temp_path: &Path, hash: &str) -> Result<(PathBuf, bool), Error> {
+        let object = self.object_path(hash);
+        let msg = || object.to_string_lossy().to_string();
+        if tokio::fs::try_exists(&object)
+            .await
+            .with_context(|_| ObjectSnafu { msg: msg() })?
+        {
+            debug!("Deduplicated {:?}", object);
+            tokio::fs::remove_file(temp_path)
+                .await
+                .with_context(|_| ObjectSnafu { msg: msg() })?;
+            return Ok((object, false));
+        }
+
+        let fan_out = object.parent().unwrap_or(&self.folder);
+        create_dirs(fan_out, &self.options)
+            .await
+            .with_context(|_| ObjectSnafu { msg: msg() })?;
+        tokio::fs::rename(temp_path, &object)
+            .await
+            .with_context(|_| ObjectSnafu { msg: msg() })?;
+        self.sync_parent(&object).await?;
+        Ok((object, true))
+    }
+
+    /// The file holding the data of an upload: the object for pointer files,
+    /// the stored file itself otherwise.
+    /// path: The path the file was uploaded to.
+    pub async fn resolve(&self, path: impl AsRef<Path>) -> Result<PathBuf, Error> {
+        let path = RelativePath::new(path)?.to_path(&self.folder);
+        self.content_path(&path).await
+    }
+
+    /// Like [`Local::resolve`], for an absolute path below the folder.
+    pub(super) async fn content_path(&self, path: &Path) -> Result<PathBuf, Error> {
+        if self.content_addressing != Some(ContentAddressing::Pointers) {
+            return Ok(path.to_path_buf());
+        }
+        let msg = || path.to_string_lossy().to_string();
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .with_context(|_| ObjectSnafu { msg: msg() })?;
+        if metadata.len() > MAX_POINTER_SIZE {
+            return Ok(path.to_path_buf());
+        }
+        let content = tokio::fs::read(path)
+            .await
+            .with_context(|_| ObjectSnafu { msg: msg() })?;
+        Ok(parse_pointer(&content)
+            .map(|hash| self.object_path(hash))
+            .unwrap_or_else(|| path.to_path_buf()))
+    }
+
+    /// Delete the objects no upload path links or points to anymore,
+    /// including the previous versions. Uploads wait until it is done.
+    pub async fn collect_garbage(&self) -> Result<GarbageCollection, Error> {
+        let _guard = self.gc_lock.write().await;
+        let folder = self.folder.clone();
+        let collection = tokio::task::spawn_blocking(move || {
+            let mut pointers = HashSet::new();
+            find_pointers(&folder, true, &mut pointers)?;
+            remove_unreferenced(&folder.join(OBJECTS_DIR), &pointers)
+        })
+        .await
+        .map_err(io::Error::other)
+        .and_then(|result| result)
+        .with_context(|_| ObjectSnafu {
+            msg: self.folder.join(OBJECTS_DIR).to_string_lossy().to_string(),
+        })?;
+        self.account(0, collection.freed);
+        Ok(collection)
+    }
+}
+
+/// The hash in the content of a pointer file.
+fn parse_pointer(content: &[u8]) -> Option<&str> {
+    let hash = std::str::from_utf8(content)
+        .ok()?
+        .strip_prefix(POINTER_PREFIX)?
+        .trim_end();
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit())).then_some(hash)
+}
+
+/// Collect the hashes of the pointer files below `dir`, skipping the objects.
+fn find_pointers(dir: &Path, top: bool, pointers: &mut HashSet<String>) -> io::Result<()> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if top && entry.file_name() == OBJECTS_DIR {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            find_pointers(&entry.path(), false, pointers)?;
+        } else if metadata.is_file() && metadata.len() <= MAX_POINTER_SIZE {
+            if let Some(hash) = parse_pointer(&std::fs::read(entry.path())?) {
+                pointers.insert(hash.to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn remove_unreferenced(
+    objects: &Path,
+    pointers: &HashSet<String>,
+) -> io::Result<GarbageCollection> {
+    let mut collection = GarbageCollection::default();
+    let fan_outs = match std::fs::read_dir(objects) {
+        Ok(fan_outs) => fan_outs,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(collection),
+        Err(e) => return Err(e),
+    };
+    for fan_out in fan_outs {
+        let fan_out = fan_out?;
+        let prefix = fan_out.file_name().to_string_lossy().to_string();
+        // Only the files the object store wrote are deleted
+        if !fan_out.file_type()?.is_dir() || !is_hex(&prefix, 2) {
+            continue;
+        }
+        for object in std::fs::read_dir(fan_out.path())? {
+            let object = object?;
+            let name = object.file_name().to_string_lossy().to_string();
+            let metadata = object.metadata()?;
+            if !metadata.is_file() || !is_hex(&name, 62) {
+                continue;
+            }
+            let hash = format!("{}{}", prefix, name);
+            if pointers.contains(&hash) || links(&metadata) > 1 {
+                continue;
+            }
+            debug!("Removing unreferenced object {}", hash);
+            std::fs::remove_file(object.path())?;
+            collection.removed += 1;
+            collection.freed += metadata.len();
+        }
+        // Only succeeds once all objects of the fan-out are gone
+        let _ = std::fs::remove_dir(fan_out.path());
+    }
+    Ok(collection)
+}
+
+/// Whether `name` is `len` lowercase hex digits, like the names of objects.
+fn is_hex(name: &str, len: usize) -> bool {
+    name.len() == len && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(unix)]
+fn links(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt as _;
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &std::fs::Metadata) -> u64 {
+    // The link count isn't available, keep every object
+    u64::MAX
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read as _},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest as _, Sha256};
+use snafu::ResultExt;
+use tracing::debug;
+
+use super::{file_len, fs::set_owner, temp_path, ChecksumSnafu, Error, Local};
+use crate::{hex, path::RelativePath};
+
+/// Where the checksums of uploaded files are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumLayout {
+    /// A file next to each upload, e.g. `db.tar.sha256`.
+    /// Uploads to the checksum file of a stored file are rejected, as are
+    /// uploads whose checksum file would replace a file that isn't one.
+    Sidecar,
+    /// One file per directory with a line per upload, e.g. `SHA256SUMS`.
+    /// Uploads named like a manifest are rejected.
+    Manifest,
+}
+
+/// Checksums written for every upload, in the format of `sha256sum` and
+/// `b3sum` so they can be checked with those tools too.
+#[derive(Debug, Clone)]
+pub struct Checksums {
+    layout: ChecksumLayout,
+    blake3: bool,
+}
+
+impl Checksums {
+    /// Write the SHA-256 of every upload.
+    pub fn new(layout: ChecksumLayout) -> Self {
+        Self {
+            layout,
+            blake3: false,
+        }
+    }
+
+    /// Write the BLAKE3 hash of every upload as well.
+    #[cfg(feature = "blake3")]
+    pub fn with_blake3(mut self, blake3: bool) -> Self {
+        self.blake3 = blake3;
+        self
+    }
+
+    fn algorithms(&self) -> impl Iterator<Item = Algorithm> {
+        [
+            Some(Algorithm::Sha256),
+            self.blake3.then_some(Algorithm::Blake3),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Whether an upload to `path` would clash with a checksum file.
+    pub(super) async fn is_reserved(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        for algorithm in self.algorithms() {
+            let reserved = match self.layout {
+                ChecksumLayout::Sidecar => self.clashes_with_sidecar(path, &name, algorithm).await,
+                ChecksumLayout::Manifest => name == algorithm.manifest(),
+            };
+            if reserved {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether `path` is the sidecar of a stored file, or its own sidecar
+    /// would replace a file which isn't one.
+    async fn clashes_with_sidecar(&self, path: &Path, name: &str, algorithm: Algorithm) -> bool {
+        if let Some(stem) = name.strip_suffix(algorithm.extension()) {
+            let stored = tokio::fs::metadata(path.with_file_name(stem)).await;
+            if stored.is_ok_and(|metadata| metadata.is_file()) {
+                return true;
+            }
+        }
+        match read_checksum_file(&self.checksum_path(path, algorithm)).await {
+            Ok(lines) => !lines.contains_key(name),
+            Err(e) => e.kind() != io::ErrorKind::NotFound,
+        }
+    }
+
+    /// The file the checksum of `path` is kept in.
+    fn checksum_path(&self, path: &Path, algorithm: Algorithm) -> PathBuf {
+        match self.layout {
+            ChecksumLayout::Sidecar => {
+                let mut name = path.file_name().unwrap_or_default().to_os_string();
+                name.push(algorithm.extension());
+                path.with_file_name(name)
+            }
+            ChecksumLayout::Manifest => path.with_file_name(algorithm.manifest()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => ".sha256",
+            Algorithm::Blake3 => ".b3",
+        }
+    }
+
+    fn manifest(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA256SUMS",
+            Algorithm::Blake3 => "B3SUMS",
+        }
+    }
+}
+
+/// Hashes the data of an upload while it is written.
+pub(super) struct Hasher {
+    sha256: Sha256,
+    #[cfg(feature = "blake3")]
+    blake3: Option<blake3::Hasher>,
+}
+
+impl Hasher {
+    /// Without checksums, only the SHA-256 is computed.
+    pub(super) fn new(checksums: Option<&Checksums>) -> Self {
+        #[cfg(not(feature = "blake3"))]
+        let _ = checksums;
+        Self {
+            sha256: Sha256::new(),
+            #[cfg(feature = "blake3")]
+            blake3: checksums
+                .is_some_and(|checksums| checksums.blake3)
+                .then(blake3::Hasher::new),
+        }
+    }
+
+    pub(super) fn update(&mut self, data: &[u8]) {
+        self.sha256.update(data);
+        #[cfg(feature = "blake3")]
+        if let Some(blake3) = &mut self.blake3 {
+            blake3.update(data);
+        }
+    }
+
+    pub(super) fn finish(self) -> Digests {
+        let mut digests = BTreeMap::new();
+        digests.insert(Algorithm::Sha256, hex(&self.sha256.finalize()));
+        #[cfg(feature = "blake3")]
+        if let Some(blake3) = self.blake3 {
+            digests.insert(Algorithm::Blake3, blake3.finalize().to_hex().to_string());
+        }
+        Digests(digests)
+    }
+}
+
+/// The hex encoded hashes of an upload.
+pub(super) struct Digests(BTreeMap<Algorithm, String>);
+
+impl Digests {
+    pub(super) fn sha256(&self) -> &str {
+        // Always computed, see `Hasher::finish`
+        &self.0[&Algorithm::Sha256]
+    }
+}
+
+/// Hash a written file, for data the process didn't read itself.
+pub(super) async fn hash_file(path: &Path, checksums: Option<&Checksums>) -> io::Result<Digests> {
+    let (path, mut hasher) = (path.to_path_buf(), Hasher::new(checksums));
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut buf = vec![0; 256 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok(hasher.finish()),
+                n => hasher.update(&buf[..n]),
+            }
+        }
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+impl Local {
+    /// Write the checksums of an upload at `path` next to it.
+    pub(super) async fn write_checksums(
+        &self,
+        checksums: &Checksums,
+        path: &Path,
+        digests: Digests,
+    ) -> Result<(), Error> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // Uploads to the same directory would lose each other's manifest lines
+        let _guard = self.manifest_lock.lock().await;
+        for (algorithm, digest) in digests.0 {
+            let checksum_path = checksums.checksum_path(path, algorithm);
+            let msg = || checksum_path.to_string_lossy().to_string();
+            let mut lines = match checksums.layout {
+                ChecksumLayout::Sidecar => BTreeMap::new(),
+                ChecksumLayout::Manifest => match read_checksum_file(&checksum_path).await {
+                    Ok(lines) => lines,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                    Err(e) => return Err(e).with_context(|_| ChecksumSnafu { msg: msg() }),
+                },
+            };
+            lines.insert(name.to_string(), digest);
+
+            let content: String = lines
+                .iter()
+                .map(|(name, digest)| format!("{}  {}\n", digest, name))
+                .collect();
+            let (added, removed) = (content.len() as u64, file_len(&checksum_path).await);
+            let temp_path = temp_path(&checksum_path);
+            let result = async {
+                tokio::fs::write(&temp_path, content).await?;
+                set_owner(&temp_path, self.options.group, self.options.file_mode).await?;
+                tokio::fs::rename(&temp_path, &checksum_path).await
+            }
+            .await;
+            if result.is_err() {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+            }
+            result.with_context(|_| ChecksumSnafu { msg: msg() })?;
+            self.account(added, removed);
+            debug!("Wrote checksum of {:?} to {:?}", path, checksum_path);
+        }
+
+        self.sync_parent(path).await
+    }
+
+    /// Hash a stored file again and compare it to the checksums written on upload.
+    /// Fails with [`Error::ChecksumMismatch`] if the file changed since.
+    pub async fn verify(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let Some(checksums) = &self.checksums else {
+            return Err(Error::MissingChecksum {
+                msg: path.as_ref().to_string_lossy().to_string(),
+            });
+        };
+        let path = RelativePath::new(path)?.to_path(&self.folder);
+        let msg = || path.to_string_lossy().to_string();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+        let content = self.content_path(&path).await?;
+        let digests = hash_file(&content, Some(checksums))
+            .await
+            .with_context(|_| ChecksumSnafu { msg: msg() })?;
+        for (algorithm, actual) in digests.0 {
+            let checksum_path = checksums.checksum_path(&path, algorithm);
+            let expected = match read_checksum_file(&checksum_path).await {
+                Ok(mut lines) => lines.remove(name.as_ref()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    return Err(e).with_context(|_| ChecksumSnafu {
+                        msg: checksum_path.to_string_lossy().to_string(),
+                    })
+                }
+            };
+            let Some(expected) = expected else {
+                return Err(Error::MissingChecksum { msg: msg() });
+            };
+            if !expected.eq_ignore_ascii_case(&actual) {
+                return Err(Error::ChecksumMismatch {
+                    msg: msg(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the `<digest>  <name>` lines of a checksum file by name.
+async fn read_checksum_file(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        // `*` marks binary mode in `sha256sum` output
+        .map(|(digest, name)| {
+            (
+                name.trim_start_matches([' ', '*']).to_string(),
+                digest.to_string(),
+            )
+        })
+        .collect())
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs::File,
+    io::{self, Seek as _},
+    path::Path,
+};
+
+use tracing::debug;
+
+/// Copy `source` into a new file at `dest` without passing the data through
+/// userspace where possible: a reflink on btrfs and XFS, `copy_file_range`
+/// within a filesystem, a streamed copy otherwise.
+/// With `sparse`, only the data segments of `source` are copied and its holes
+/// stay holes in `dest` (Linux only).
+pub(super) fn copy_file(source: &Path, dest: &Path, sparse: bool) -> io::Result<File> {
+    let mut source = File::open(source)?;
+    let mut dest = File::create(dest)?;
+
+    #[cfg(target_os = "linux")]
+    {
+        if reflink(&source, &dest).is_ok() {
+            debug!("Cloned {:?}", dest);
+            return Ok(dest);
+        }
+        let len = source.metadata()?.len();
+        if sparse {
+            copy_data_segments(&source, &dest, len)?;
+            debug!("Copied the data segments of {:?}", dest);
+            return Ok(dest);
+        }
+        match copy_range(&source, &dest, 0, len) {
+            Ok(()) => {
+                debug!("Copied {:?} in the kernel", dest);
+                return Ok(dest);
+            }
+            // Not supported between these filesystems, start over with a plain copy
+            Err(e) if is_unsupported(&e) => {
+                dest.set_len(0)?;
+                dest.rewind()?;
+                source.rewind()?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = sparse;
+
+    io::copy(&mut source, &mut dest)?;
+    Ok(dest)
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &File, dest: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    if unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Copy the bytes `start..end` of `source` to the same offsets in `dest`.
+#[cfg(target_os = "linux")]
+fn copy_range(source: &File, dest: &File, start: u64, end: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd as _;
+
+    let (mut off_in, mut off_out) = (start as libc::loff_t, start as libc::loff_t);
+    while (off_in as u64) < end {
+        let chunk = (end - off_in as u64).min(1 << 30) as usize;
+        // The kernel advances both offsets
+        let copied = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                &mut off_in,
+                dest.as_raw_fd(),
+                &mut off_out,
+                chunk,
+                0,
+            )
+        };
+        match copied {
+            -1 => return Err(io::Error::last_os_error()),
+            // The source shrank while copying
+            0 => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copy the data segments found with `SEEK_DATA`/`SEEK_HOLE` and leave the
+/// rest of `dest` unallocated.
+#[cfg(target_os = "linux")]
+fn copy_data_segments(source: &File, dest: &File, len: u64) -> io::Result<()> {
+    let mut offset = 0;
+    while offset < len {
+        let start = match seek(source, offset, libc::SEEK_DATA) {
+            Ok(start) => start,
+            // Only a hole is left
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => break,
+            Err(e) => return Err(e),
+        };
+        let end = seek(source, start, libc::SEEK_HOLE)?.min(len);
+        match copy_range(source, dest, start, end) {
+            Ok(()) => {}
+            Err(e) if is_unsupported(&e) => write_range(source, dest, start, end)?,
+            Err(e) => return Err(e),
+        }
+        offset = end;
+    }
+    // A hole at the end has no data segment to extend the file
+    dest.set_len(len)
+}
+
+#[cfg(target_os = "linux")]
+fn seek(file: &File, offset: u64, whence: libc::c_int) -> io::Result<u64> {
+    use std::os::fd::AsRawFd as _;
+
+    match unsafe { libc::lseek(file.as_raw_fd(), offset as libc::off_t, whence) } {
+        -1 => Err(io::Error::last_os_error()),
+        offset => Ok(offset as u64),
+    }
+}
+
+/// Copy the bytes `start..end` through userspace.
+#[cfg(target_os = "linux")]
+fn write_range(source: &File, dest: &File, start: u64, end: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt as _;
+
+    let mut buf = vec![0; 256 * 1024];
+    let mut offset = start;
+    while offset < end {
+        let chunk = (end - offset).min(buf.len() as u64) as usize;
+        let n = source.read_at(&mut buf[..chunk], offset)?;
+        if n == 0 {
+            break;
+        }
+        dest.write_all_at(&buf[..n], offset)?;
+        offset += n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn is_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EXDEV | libc::ENOSYS | libc::EOPNOTSUPP | libc::EINVAL)
+    )
+}
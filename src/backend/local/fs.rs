@@ -0,0 +1,265 @@
+use std::{io::SeekFrom, path::Path};
+
+use snafu::ResultExt;
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+};
+
+use super::{checksum::Hasher, CopyDataSnafu, CreateFileSnafu, Error, SetMetadataSnafu, SyncSnafu};
+use crate::{AsyncBufReadSeek, Metadata, Quota};
+
+#[cfg(unix)]
+// The field types of `statvfs` differ between platforms
+#[allow(clippy::unnecessary_cast)]
+pub(super) fn statvfs(path: &Path) -> std::io::Result<Quota> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+
+    let block_size = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block_size;
+    let free = stat.f_bfree as u64 * block_size;
+    Ok(Quota {
+        total,
+        used: total - free,
+        remaining: stat.f_bavail as u64 * block_size,
+        deleted: None,
+    })
+}
+
+#[cfg(not(unix))]
+pub(super) fn statvfs(_path: &Path) -> std::io::Result<Quota> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+/// How [`Local`](super::Local) writes files and directories.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct FileOptions {
+    pub xattrs: bool,
+    pub sparse: bool,
+    /// Overrides the permissions of the metadata.
+    pub file_mode: Option<u32>,
+    pub dir_mode: Option<u32>,
+    pub group: Option<u32>,
+    pub sync: SyncPolicy,
+}
+
+/// What is flushed to disk before an upload counts as done.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave it to the system, a crash can lose recent uploads.
+    None,
+    /// Flush the data of the file.
+    File,
+    /// Flush the data of the file and the directory entry pointing to it.
+    #[default]
+    FileAndDir,
+}
+
+/// Blocks of zeros this large are left as holes in sparse files.
+const SPARSE_BLOCK_SIZE: u64 = 4096;
+
+/// Copy the data into a new file, apply the metadata and flush it to disk.
+/// The hasher sees every byte written. With `sparse`, blocks of zeros are
+/// skipped instead of written. Writing stops with an error once the data
+/// exceeds `budget`.
+pub(super) async fn write_file(
+    reader: &mut Box<dyn AsyncBufReadSeek>,
+    path: &Path,
+    metadata: &Metadata,
+    options: &FileOptions,
+    mut hasher: Option<&mut Hasher>,
+    budget: Option<u64>,
+) -> Result<(), Error> {
+    let mut file = File::create(path).await.with_context(|_| CreateFileSnafu {
+        msg: path.to_string_lossy().to_string(),
+    })?;
+    let mut buf = vec![0; 256 * 1024];
+    let mut offset = 0;
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .with_context(|_| CopyDataSnafu {
+                msg: path.to_string_lossy().to_string(),
+            })?;
+        if n == 0 {
+            break;
+        }
+        if let Some(max) = budget.filter(|max| offset + n as u64 > *max) {
+            return Err(Error::FileTooLarge {
+                msg: path.to_string_lossy().to_string(),
+                size: offset + n as u64,
+                max,
+            });
+        }
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&buf[..n]);
+        }
+        let result = match options.sparse {
+            true => write_sparse(&mut file, offset, &buf[..n]).await,
+            false => file.write_all(&buf[..n]).await,
+        };
+        result.with_context(|_| CopyDataSnafu {
+            msg: path.to_string_lossy().to_string(),
+        })?;
+        offset += n as u64;
+    }
+    // Skipped zeros at the end don't extend the file
+    if options.sparse {
+        file.set_len(offset).await.with_context(|_| CopyDataSnafu {
+            msg: path.to_string_lossy().to_string(),
+        })?;
+    }
+
+    finish_file(file.into_std().await, path, metadata, options).await
+}
+
+/// Write `data` at `offset`, leaving out the blocks of zeros.
+async fn write_sparse(file: &mut File, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    // Blocks are aligned to the file, not to the buffer
+    let block_end = |i: usize| {
+        let end = ((offset + i as u64) / SPARSE_BLOCK_SIZE + 1) * SPARSE_BLOCK_SIZE - offset;
+        (end as usize).min(data.len())
+    };
+    let is_zero = |i: usize| data[i..block_end(i)].iter().all(|b| *b == 0);
+
+    let mut start = 0;
+    while start < data.len() {
+        // Extend the run while the blocks are of the same kind
+        let zero = is_zero(start);
+        let mut end = block_end(start);
+        while end < data.len() && is_zero(end) == zero {
+            end = block_end(end);
+        }
+        if !zero {
+            file.seek(SeekFrom::Start(offset + start as u64)).await?;
+            file.write_all(&data[start..end]).await?;
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// Apply the metadata to a written file and flush it to disk.
+pub(super) async fn finish_file(
+    file: std::fs::File,
+    path: &Path,
+    metadata: &Metadata,
+    options: &FileOptions,
+) -> Result<(), Error> {
+    let (metadata, options) = (metadata.clone(), *options);
+    let msg = path.to_string_lossy().to_string();
+    tokio::task::spawn_blocking(move || {
+        set_metadata(&file, &metadata, &options).context(SetMetadataSnafu { msg: &msg })?;
+        if options.sync == SyncPolicy::None {
+            return Ok(());
+        }
+        file.sync_all().context(SyncSnafu { msg })
+    })
+    .await
+    .map_err(std::io::Error::other)
+    .with_context(|_| SetMetadataSnafu {
+        msg: path.to_string_lossy().to_string(),
+    })?
+}
+
+fn set_metadata(
+    file: &std::fs::File,
+    metadata: &Metadata,
+    options: &FileOptions,
+) -> std::io::Result<()> {
+    // Changing the owner clears setuid and setgid, so it comes first
+    #[cfg(unix)]
+    if let Some(group) = options.group {
+        std::os::unix::fs::fchown(file, None, Some(group))?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = options.file_mode.or(metadata.permissions) {
+        use std::os::unix::fs::PermissionsExt as _;
+        file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+    }
+
+    let mut times = std::fs::FileTimes::new();
+    if let Some(modified) = metadata.modified {
+        times = times.set_modified(modified);
+    }
+    if let Some(accessed) = metadata.accessed {
+        times = times.set_accessed(accessed);
+    }
+    file.set_times(times)?;
+
+    #[cfg(unix)]
+    if options.xattrs {
+        use xattr::FileExt as _;
+        // Other namespaces need privileges or have a meaning to the system
+        for (name, value) in metadata
+            .xattrs
+            .iter()
+            .filter(|(name, _)| name.starts_with("user."))
+        {
+            file.set_xattr(name, value)?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = options;
+    Ok(())
+}
+
+/// Create `path` and its missing parents with the mode and group of the options.
+/// Without either, this is `create_dir_all`.
+pub(super) async fn create_dirs(path: &Path, options: &FileOptions) -> std::io::Result<()> {
+    if options.dir_mode.is_none() && options.group.is_none() {
+        return tokio::fs::create_dir_all(path).await;
+    }
+    let missing: Vec<_> = path.ancestors().take_while(|p| !p.exists()).collect();
+    for dir in missing.into_iter().rev() {
+        match tokio::fs::create_dir(dir).await {
+            Ok(()) => {}
+            // Created by a concurrent upload
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+        set_owner(dir, options.group, options.dir_mode).await?;
+    }
+    Ok(())
+}
+
+/// Change the group and mode of a file or directory the backend created.
+/// Unlike the mode given to `open` or `mkdir`, this isn't reduced by the umask.
+pub(super) async fn set_owner(
+    path: &Path,
+    group: Option<u32>,
+    mode: Option<u32>,
+) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        if let Some(group) = group {
+            std::os::unix::fs::chown(path, None, Some(group))?;
+        }
+        if let Some(mode) = mode {
+            use std::os::unix::fs::PermissionsExt as _;
+            tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).await?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (path, group, mode);
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(super) async fn sync_dir(path: &Path) -> std::io::Result<()> {
+    File::open(path).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+pub(super) async fn sync_dir(_path: &Path) -> std::io::Result<()> {
+    // Directories can't be opened as files on other platforms
+    Ok(())
+}
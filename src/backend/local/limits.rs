@@ -0,0 +1,187 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+use snafu::ResultExt;
+
+use super::{Error, Local, QuotaSnafu};
+
+/// Limits on the space uploads may take. Each is checked against the size of
+/// an upload before writing, and the written data is stopped at the tightest
+/// one in case the reader yields more than announced.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    max_total_size: Option<u64>,
+    min_free_space: Option<u64>,
+    max_file_size: Option<u64>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The most bytes all files below the folder may take together.
+    /// The folder is measured on the first upload, files changed by other
+    /// processes afterwards aren't noticed. Hardlinks and stored objects
+    /// are counted once.
+    pub fn max_total_size(mut self, bytes: u64) -> Self {
+        self.max_total_size = Some(bytes);
+        self
+    }
+
+    /// The bytes to leave free on the filesystem of the folder.
+    pub fn min_free_space(mut self, bytes: u64) -> Self {
+        self.min_free_space = Some(bytes);
+        self
+    }
+
+    /// The largest file that may be uploaded.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+}
+
+/// Space set aside for an upload until it is stored or failed.
+pub(super) struct Reservation<'a> {
+    usage: Option<&'a Mutex<Option<u64>>>,
+    size: u64,
+    /// The most bytes the upload may write.
+    pub(super) budget: Option<u64>,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        if let Some(usage) = self.usage {
+            if let Some(used) = lock(usage).as_mut() {
+                *used = used.saturating_sub(self.size);
+            }
+        }
+    }
+}
+
+impl Local {
+    /// Check the limits for an upload of `size` bytes to `path` and reserve
+    /// the space against the total size.
+    pub(super) async fn reserve(&self, path: &Path, size: u64) -> Result<Reservation<'_>, Error> {
+        let mut reservation = Reservation {
+            usage: None,
+            size,
+            budget: None,
+        };
+        let Some(limits) = &self.limits else {
+            return Ok(reservation);
+        };
+        let msg = || path.to_string_lossy().to_string();
+        let tighten = |budget: Option<u64>, max: u64| Some(budget.map_or(max, |b| b.min(max)));
+
+        if let Some(max) = limits.max_file_size {
+            if size > max {
+                return Err(Error::FileTooLarge {
+                    msg: msg(),
+                    size,
+                    max,
+                });
+            }
+            reservation.budget = tighten(reservation.budget, max);
+        }
+
+        if let Some(min_free) = limits.min_free_space {
+            if let Some(quota) = self.quota()? {
+                let remaining = quota.remaining.saturating_sub(min_free);
+                if size > remaining {
+                    return Err(Error::InsufficientStorage {
+                        msg: msg(),
+                        size,
+                        remaining,
+                    });
+                }
+                reservation.budget = tighten(reservation.budget, remaining);
+            }
+        }
+
+        if let Some(max) = limits.max_total_size {
+            if lock(&self.usage).is_none() {
+                let folder = self.folder.clone();
+                let measured = tokio::task::spawn_blocking(move || folder_size(&folder))
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|result| result)
+                    .with_context(|_| QuotaSnafu {
+                        msg: self.folder.to_string_lossy().to_string(),
+                    })?;
+                // Another upload may have measured it meanwhile
+                lock(&self.usage).get_or_insert(measured);
+            }
+
+            let mut used = lock(&self.usage);
+            let used = used.get_or_insert(0);
+            let available = max.saturating_sub(*used);
+            if size > available {
+                return Err(Error::QuotaExceeded {
+                    msg: msg(),
+                    size,
+                    available,
+                });
+            }
+            *used += size;
+            reservation.usage = Some(&self.usage);
+            reservation.budget = tighten(reservation.budget, available);
+        }
+        Ok(reservation)
+    }
+
+    /// Count a stored file against the total size, replacing the one before.
+    pub(super) fn account(&self, added: u64, removed: u64) {
+        if let Some(used) = lock(&self.usage).as_mut() {
+            *used = (*used + added).saturating_sub(removed);
+        }
+    }
+}
+
+/// The usage is a plain number, it can't be left inconsistent by a panic.
+fn lock(usage: &Mutex<Option<u64>>) -> MutexGuard<'_, Option<u64>> {
+    usage.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The size of the files below `path`, without following symlinks.
+/// Hardlinked files are counted once.
+fn folder_size(path: &Path) -> io::Result<u64> {
+    add_folder_size(path, &mut HashSet::new())
+}
+
+fn add_folder_size(path: &Path, seen: &mut HashSet<(u64, u64)>) -> io::Result<u64> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += add_folder_size(&entry.path(), seen)?;
+        } else if metadata.is_file() && first_link(&metadata, seen) {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Whether `metadata` is of the first link to a file seen.
+#[cfg(unix)]
+fn first_link(metadata: &std::fs::Metadata, seen: &mut HashSet<(u64, u64)>) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+    metadata.nlink() <= 1 || seen.insert((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn first_link(_metadata: &std::fs::Metadata, _seen: &mut HashSet<(u64, u64)>) -> bool {
+    // The file identity isn't available, count every link
+    true
+}
@@ -0,0 +1,1071 @@
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use snafu::{ResultExt, Snafu};
+use tracing::debug;
+
+use crate::{
+    path::{Reason, RelativePath},
+    unique_id, AsyncBufReadSeek, Backend, Metadata, Quota,
+};
+use cas::OBJECTS_DIR;
+pub use cas::{ContentAddressing, GarbageCollection};
+use checksum::{hash_file, Digests, Hasher};
+pub use checksum::{ChecksumLayout, Checksums};
+pub use fs::SyncPolicy;
+use fs::{create_dirs, statvfs, sync_dir, write_file, FileOptions};
+pub use limits::Limits;
+use limits::Reservation;
+use versions::VERSIONS_DIR;
+pub use versions::{Version, Versioning};
+
+mod cas;
+mod checksum;
+mod copy;
+mod fs;
+mod limits;
+mod versions;
+
+pub struct Local {
+    folder: PathBuf,
+    check_quota: bool,
+    options: FileOptions,
+    hardlinks: bool,
+    versioning: Option<Versioning>,
+    checksums: Option<Checksums>,
+    limits: Option<Limits>,
+    content_addressing: Option<ContentAddressing>,
+    manifest_lock: tokio::sync::Mutex<()>,
+    /// Bytes stored and reserved below the folder, measured on the first upload.
+    usage: std::sync::Mutex<Option<u64>>,
+    gc_lock: tokio::sync::RwLock<()>,
+}
+
+impl Local {
+    /// Create a new instance of the local backend.
+    /// folder: The folder where the files will be stored.
+    pub fn new(folder: PathBuf) -> Self {
+        Self {
+            folder,
+            check_quota: false,
+            options: FileOptions {
+                xattrs: false,
+                sparse: false,
+                file_mode: None,
+                dir_mode: None,
+                group: None,
+                sync: SyncPolicy::default(),
+            },
+            hardlinks: false,
+            versioning: None,
+            checksums: None,
+            limits: None,
+            content_addressing: None,
+            manifest_lock: tokio::sync::Mutex::new(()),
+            usage: std::sync::Mutex::new(None),
+            gc_lock: tokio::sync::RwLock::new(()),
+        }
+    }
+
+    /// Check the free space of the filesystem before writing a file.
+    pub fn with_quota_check(mut self, check_quota: bool) -> Self {
+        self.check_quota = check_quota;
+        self
+    }
+
+    /// Set the `user.*` extended attributes of uploads with metadata.
+    /// The filesystem must support them, other attributes are ignored.
+    pub fn with_xattrs(mut self, xattrs: bool) -> Self {
+        self.options.xattrs = xattrs;
+        self
+    }
+
+    /// Set the permissions of stored files, e.g. `0o664`, instead of the ones
+    /// of the upload metadata or the umask.
+    pub fn with_file_mode(mut self, mode: u32) -> Self {
+        self.options.file_mode = Some(mode);
+        self
+    }
+
+    /// Set the permissions of created directories, e.g. `0o2775`, instead of
+    /// the ones from the umask. Existing directories are left alone.
+    pub fn with_dir_mode(mut self, mode: u32) -> Self {
+        self.options.dir_mode = Some(mode);
+        self
+    }
+
+    /// Change the group of stored files and created directories to `gid`.
+    /// The process must own them and be a member of the group.
+    pub fn with_group(mut self, gid: u32) -> Self {
+        self.options.group = Some(gid);
+        self
+    }
+
+    /// What to flush to disk before an upload returns, everything by default.
+    pub fn with_sync(mut self, sync: SyncPolicy) -> Self {
+        self.options.sync = sync;
+        self
+    }
+
+    /// Hardlink files uploaded with [`Backend::upload_file`] instead of copying
+    /// them when they are on the same filesystem. Changes to the source then
+    /// show up in the stored file, and its metadata is the one of the source,
+    /// the file mode and group are not applied.
+    pub fn with_hardlinks(mut self, hardlinks: bool) -> Self {
+        self.hardlinks = hardlinks;
+        self
+    }
+
+    /// Leave blocks of zeros in uploads unallocated, e.g. for disk images.
+    /// Files uploaded with [`Backend::upload_file`] keep the holes of the
+    /// source instead.
+    pub fn with_sparse_files(mut self, sparse: bool) -> Self {
+        self.options.sparse = sparse;
+        self
+    }
+
+    /// Keep the previous content of replaced files below `.versions` in the
+    /// folder, see [`Local::versions`] and [`Local::restore`].
+    /// Uploads to `.versions` itself are rejected.
+    pub fn with_versioning(mut self, versioning: Versioning) -> Self {
+        self.versioning = Some(versioning);
+        self
+    }
+
+    /// Write the checksums of every upload next to it, see [`Local::verify`].
+    /// Uploads clashing with checksum files are rejected.
+    pub fn with_checksums(mut self, checksums: Checksums) -> Self {
+        self.checksums = Some(checksums);
+        self
+    }
+
+    /// Limit the space taken by uploads, see [`Limits`].
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Store the data of uploads once per content below `.objects`, named by
+    /// their SHA-256, and link or point to it from the upload path.
+    /// Unreferenced objects are deleted by [`Local::collect_garbage`].
+    /// Uploads to `.objects` itself are rejected.
+    pub fn with_content_addressing(mut self, mode: ContentAddressing) -> Self {
+        self.content_addressing = Some(mode);
+        self
+    }
+
+    /// Validate the upload path, check the quota and limits and create the
+    /// parent directories.
+    /// Returns the absolute path of the file and the space reserved for it.
+    async fn prepare(&self, path: &Path, size: u64) -> Result<(PathBuf, Reservation<'_>), Error> {
+        let relative = RelativePath::new(path)?;
+        let top = relative.names().next();
+        let versions = self.versioning.is_some() && top == Some(VERSIONS_DIR.as_ref());
+        let objects = self.content_addressing.is_some() && top == Some(OBJECTS_DIR.as_ref());
+        let path = relative.to_path(&self.folder);
+        let checksums = match &self.checksums {
+            Some(checksums) => checksums.is_reserved(&path).await,
+            None => false,
+        };
+        if versions || objects || checksums {
+            return Err(crate::path::Error::InvalidPath {
+                path: relative.to_string(),
+                reason: if checksums {
+                    Reason::ChecksumFile
+                } else {
+                    Reason::Reserved
+                },
+            }
+            .into());
+        }
+
+        if self.check_quota {
+            if let Some(quota) = self.quota()? {
+                if quota.remaining < size {
+                    return Err(Error::InsufficientStorage {
+                        msg: path.to_string_lossy().to_string(),
+                        size,
+                        remaining: quota.remaining,
+                    });
+                }
+            }
+        }
+        let reservation = self.reserve(&path, size).await?;
+
+        // Create parent directories if they don't exist
+        let parent = path.parent().unwrap_or(&self.folder);
+        self.check_symlinks(&relative, parent).await?;
+        create_dirs(parent, &self.options)
+            .await
+            .with_context(|_| CreateDirSnafu {
+                msg: parent.to_string_lossy().to_string(),
+            })?;
+        Ok((path, reservation))
+    }
+
+    /// Rename the written temp file over `path`, or remove it if writing failed.
+    /// A crash or failed copy leaves the old file untouched.
+    async fn replace(
+        &self,
+        result: Result<(), Error>,
+        temp_path: &Path,
+        path: &Path,
+    ) -> Result<(), Error> {
+        let result = match (result, &self.versioning) {
+            (Ok(()), Some(versioning)) => self.keep_version(path, versioning).await,
+            (result, _) => result,
+        };
+        // The replaced file is gone unless it was kept as a version. Objects
+        // are counted when they are stored and when they are collected, the
+        // hardlinks to them take no space of their own.
+        let hardlinks = self.content_addressing == Some(ContentAddressing::Hardlinks);
+        let (added, removed) = match self.limits.is_some() && !hardlinks {
+            true => (
+                file_len(temp_path).await,
+                match self.versioning {
+                    Some(_) => 0,
+                    None => file_len(path).await,
+                },
+            ),
+            false => (0, 0),
+        };
+        let result = match result {
+            Ok(()) => tokio::fs::rename(temp_path, path)
+                .await
+                .with_context(|_| RenameSnafu {
+                    msg: path.to_string_lossy().to_string(),
+                }),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(temp_path).await;
+            return Err(e);
+        }
+        self.account(added, removed);
+
+        self.sync_parent(path).await
+    }
+
+    /// Persist the renames in the directory of `path` if the sync policy asks for it.
+    async fn sync_parent(&self, path: &Path) -> Result<(), Error> {
+        if self.options.sync != SyncPolicy::FileAndDir {
+            return Ok(());
+        }
+        let parent = path.parent().unwrap_or(&self.folder);
+        sync_dir(parent).await.with_context(|_| SyncSnafu {
+            msg: parent.to_string_lossy().to_string(),
+        })
+    }
+
+    /// Put a written temp file in place at `path`, through the object store if
+    /// enabled, and write its checksums.
+    async fn store(
+        &self,
+        result: Result<(), Error>,
+        temp_path: &Path,
+        path: &Path,
+        digests: Option<Digests>,
+    ) -> Result<(), Error> {
+        match self.content_addressing {
+            Some(mode) => {
+                self.store_object(mode, result, temp_path, path, digests.as_ref())
+                    .await?
+            }
+            None => self.replace(result, temp_path, path).await?,
+        }
+        self.store_checksums(path, digests).await
+    }
+
+    /// Write the checksums of a stored file if enabled. Without the digests
+    /// of the written data, the file is read again.
+    async fn store_checksums(&self, path: &Path, digests: Option<Digests>) -> Result<(), Error> {
+        let Some(checksums) = &self.checksums else {
+            return Ok(());
+        };
+        let digests = match digests {
+            Some(digests) => digests,
+            None => hash_file(&self.content_path(path).await?, Some(checksums))
+                .await
+                .with_context(|_| ChecksumSnafu {
+                    msg: path.to_string_lossy().to_string(),
+                })?,
+        };
+        self.write_checksums(checksums, path, digests).await
+    }
+
+    /// Make sure no symlink below the folder leads `parent` outside of it.
+    async fn check_symlinks(&self, relative: &RelativePath, parent: &Path) -> Result<(), Error> {
+        // Only existing directories can be symlinks, the rest is created below the folder
+        let Some(existing) = parent
+            .ancestors()
+            .take_while(|p| p.starts_with(&self.folder))
+            .find(|p| p.exists())
+        else {
+            return Ok(());
+        };
+        let canonicalize = |path: &Path| {
+            let path = path.to_path_buf();
+            async move {
+                tokio::fs::canonicalize(&path)
+                    .await
+                    .with_context(|_| CreateDirSnafu {
+                        msg: path.to_string_lossy().to_string(),
+                    })
+            }
+        };
+        let folder = canonicalize(&self.folder).await?;
+        if !canonicalize(existing).await?.starts_with(folder) {
+            return Err(crate::path::Error::InvalidPath {
+                path: relative.to_string(),
+                reason: Reason::SymlinkEscape,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Get the space of the filesystem containing the folder.
+    pub fn quota(&self) -> Result<Option<Quota>, Error> {
+        // The folder is created on the first upload, use the closest existing ancestor
+        let Some(path) = self.folder.ancestors().find(|p| p.exists()) else {
+            return Ok(None);
+        };
+        statvfs(path).map(Some).with_context(|_| QuotaSnafu {
+            msg: path.to_string_lossy().to_string(),
+        })
+    }
+}
+
+async fn file_len(path: &Path) -> u64 {
+    tokio::fs::metadata(path).await.map_or(0, |m| m.len())
+}
+
+/// A unique, hidden name next to `path` to write into.
+fn temp_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        unique_id()
+    ))
+}
+
+#[async_trait]
+impl Backend for Local {
+    async fn upload(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: PathBuf,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        self.upload_with_metadata(reader, size, path, Metadata::default())
+            .await
+    }
+
+    async fn upload_with_metadata(
+        &self,
+        mut reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: PathBuf,
+        metadata: Metadata,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        debug!("Uploading file to local: {:?}", &path);
+        let (path, reservation) = self.prepare(&path, size).await?;
+
+        let temp_path = temp_path(&path);
+        let mut hasher = (self.checksums.is_some() || self.content_addressing.is_some())
+            .then(|| Hasher::new(self.checksums.as_ref()));
+        let result = write_file(
+            &mut reader,
+            &temp_path,
+            &metadata,
+            &self.options,
+            hasher.as_mut(),
+            reservation.budget,
+        )
+        .await;
+        let digests = hasher.map(Hasher::finish);
+        Ok(self.store(result, &temp_path, &path, digests).await?)
+    }
+
+    async fn upload_file(
+        &self,
+        source: PathBuf,
+        path: PathBuf,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        debug!("Copying file {:?} to local: {:?}", &source, &path);
+        let source_metadata =
+            tokio::fs::metadata(&source)
+                .await
+                .with_context(|_| ReadSourceSnafu {
+                    msg: source.to_string_lossy().to_string(),
+                })?;
+        let (path, _reservation) = self.prepare(&path, source_metadata.len()).await?;
+        let temp_path = temp_path(&path);
+
+        // A hardlink shares the data and metadata with the source,
+        // the object store must not
+        if self.hardlinks
+            && self.content_addressing.is_none()
+            && tokio::fs::hard_link(&source, &temp_path).await.is_ok()
+        {
+            debug!("Linked {:?} to {:?}", &source, &path);
+            return Ok(self.store(Ok(()), &temp_path, &path, None).await?);
+        }
+
+        let result = async {
+            let (from, to, sparse) = (source.clone(), temp_path.clone(), self.options.sparse);
+            let file = tokio::task::spawn_blocking(move || copy::copy_file(&from, &to, sparse))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|result| result)
+                .with_context(|_| CopyDataSnafu {
+                    msg: temp_path.to_string_lossy().to_string(),
+                })?;
+            let metadata = Metadata::from(&source_metadata);
+            fs::finish_file(file, &temp_path, &metadata, &self.options).await
+        }
+        .await;
+        Ok(self.store(result, &temp_path, &path, None).await?)
+    }
+
+    async fn quota(&self) -> Result<Option<Quota>, Box<dyn snafu::Error>> {
+        Ok(Local::quota(self)?)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(transparent)]
+    Path { source: crate::path::Error },
+
+    #[snafu(display("Failed to create directory {}: {}", msg, source))]
+    CreateDir {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to read source file {}: {}", msg, source))]
+    ReadSource {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to create file {}: {}", msg, source))]
+    CreateFile {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to copy data to {}: {}", msg, source))]
+    CopyData {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to set metadata of {}: {}", msg, source))]
+    SetMetadata {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to sync {} to disk: {}", msg, source))]
+    Sync {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to replace {}: {}", msg, source))]
+    Rename {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to keep version of {}: {}", msg, source))]
+    Version {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("{} is not a version of this file", msg))]
+    UnknownVersion { msg: String },
+
+    #[snafu(display("Failed to checksum {}: {}", msg, source))]
+    Checksum {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("No checksum stored for {}", msg))]
+    MissingChecksum { msg: String },
+
+    #[snafu(display("Checksum mismatch for {}: expected {}, got {}", msg, expected, actual))]
+    ChecksumMismatch {
+        msg: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[snafu(display("{} is too large: {} bytes, at most {} allowed", msg, size, max))]
+    FileTooLarge { msg: String, size: u64, max: u64 },
+
+    #[snafu(display(
+        "Quota exceeded by {}: {} bytes required, {} bytes available",
+        msg,
+        size,
+        available
+    ))]
+    QuotaExceeded {
+        msg: String,
+        size: u64,
+        available: u64,
+    },
+
+    #[snafu(display("Failed to store object for {}: {}", msg, source))]
+    Object {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display("Failed to get quota of {}: {}", msg, source))]
+    Quota {
+        source: tokio::io::Error,
+        msg: String,
+    },
+
+    #[snafu(display(
+        "Not enough space for {}: {} bytes required, {} bytes remaining",
+        msg,
+        size,
+        remaining
+    ))]
+    InsufficientStorage {
+        msg: String,
+        size: u64,
+        remaining: u64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::{fs::create_dir_all, io::Cursor};
+
+    use tokio::fs::File;
+    use tokio::io::{AsyncWriteExt as _, BufReader};
+
+    use super::Local;
+
+    #[tokio::test]
+    async fn test_upload() {
+        let folder = temp_dir::TempDir::new().unwrap().path().to_path_buf();
+
+        create_dir_all(&folder).unwrap();
+
+        let local = Box::new(Local::new(folder.clone())) as Box<dyn crate::Backend>;
+
+        // Create and write to the file
+        let mut file = File::create(folder.join("test.txt")).await.unwrap();
+        file.write_all(b"Hello, world!").await.unwrap();
+
+        // Ensure all writes are flushed to disk
+        file.sync_all().await.unwrap();
+
+        let size = file.metadata().await.unwrap().len();
+
+        // Reopen the file for reading
+        let file = File::open(folder.join("test.txt")).await.unwrap();
+        let reader = BufReader::new(file);
+
+        let result = local
+            .upload(Box::new(reader), size, "test1.txt".into())
+            .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_quota_check() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().join("data")).with_quota_check(true);
+
+        let quota = local.quota().unwrap().unwrap();
+        assert!(quota.total >= quota.used);
+        assert!(quota.total >= quota.remaining);
+
+        let reader = std::io::Cursor::new(b"Hello, world!".to_vec());
+        let result =
+            crate::Backend::upload(&local, Box::new(reader), u64::MAX, "test.txt".into()).await;
+        assert!(result.is_err());
+        assert!(!folder.path().join("data/test.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_replace() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().to_path_buf());
+        let path = folder.path().join("a/test.txt");
+
+        for content in [b"first".to_vec(), b"second".to_vec()] {
+            let size = content.len() as u64;
+            crate::Backend::upload(
+                &local,
+                Box::new(Cursor::new(content)),
+                size,
+                "a/test.txt".into(),
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        // No temporary file is left behind
+        assert_eq!(
+            std::fs::read_dir(folder.path().join("a")).unwrap().count(),
+            1
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_symlink_escape() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let outside = temp_dir::TempDir::new().unwrap();
+        create_dir_all(folder.path().join("data")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), folder.path().join("data/link")).unwrap();
+        let local = Local::new(folder.path().join("data"));
+
+        for path in ["link/x.txt", "link/sub/x.txt", "../x.txt", "/tmp/x.txt"] {
+            let reader = Cursor::new(b"x".to_vec());
+            let result = crate::Backend::upload(&local, Box::new(reader), 1, path.into()).await;
+            assert!(result.is_err(), "{}", path);
+        }
+        assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_metadata() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().to_path_buf()).with_xattrs(true);
+        let path = folder.path().join("test.txt");
+
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let mut metadata = crate::Metadata {
+            modified: Some(modified),
+            accessed: Some(modified),
+            permissions: Some(0o640),
+            ..Default::default()
+        };
+        // Not every filesystem supports user xattrs, e.g. tmpfs on older kernels
+        #[cfg(unix)]
+        let xattrs = xattr::set(folder.path(), "user.test", b"1").is_ok();
+        #[cfg(unix)]
+        if xattrs {
+            metadata
+                .xattrs
+                .insert("user.sha256".to_string(), b"abc".to_vec());
+            metadata
+                .xattrs
+                .insert("trusted.ignored".to_string(), b"abc".to_vec());
+        }
+
+        crate::Backend::upload_with_metadata(
+            &local,
+            Box::new(Cursor::new(b"Hello".to_vec())),
+            5,
+            "test.txt".into(),
+            metadata,
+        )
+        .await
+        .unwrap();
+
+        let written = std::fs::metadata(&path).unwrap();
+        assert_eq!(written.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(written.permissions().mode() & 0o7777, 0o640);
+            if xattrs {
+                assert_eq!(xattr::get(&path, "user.sha256").unwrap().unwrap(), b"abc");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_file() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let source = folder.path().join("source.img");
+        let content: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&source, &content).unwrap();
+
+        for hardlinks in [false, true] {
+            let local = Local::new(folder.path().join("data")).with_hardlinks(hardlinks);
+            crate::Backend::upload_file(&local, source.clone(), "image.img".into())
+                .await
+                .unwrap();
+
+            let stored = folder.path().join("data/image.img");
+            assert!(std::fs::read(&stored).unwrap() == content);
+            let (source, stored) = (
+                std::fs::metadata(&source).unwrap(),
+                std::fs::metadata(&stored).unwrap(),
+            );
+            assert_eq!(source.modified().unwrap(), stored.modified().unwrap());
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt as _;
+                assert_eq!(source.ino() == stored.ino(), hardlinks);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_versioning() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().to_path_buf())
+            .with_versioning(super::Versioning::new().keep_last(2));
+
+        for content in ["first", "second", "third", "fourth"] {
+            let reader = Cursor::new(content.as_bytes().to_vec());
+            let size = content.len() as u64;
+            crate::Backend::upload(&local, Box::new(reader), size, "a/test.txt".into())
+                .await
+                .unwrap();
+        }
+        let versions = local.versions("a/test.txt").await.unwrap();
+        let contents: Vec<_> = versions
+            .iter()
+            .map(|v| std::fs::read(&v.path).unwrap())
+            .collect();
+        assert_eq!(contents, [b"third".to_vec(), b"second".to_vec()]);
+
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let file = std::fs::File::options()
+            .write(true)
+            .open(&versions[1].path)
+            .unwrap();
+        file.set_modified(modified).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            file.set_permissions(std::fs::Permissions::from_mode(0o640))
+                .unwrap();
+        }
+
+        local.restore("a/test.txt", &versions[1]).await.unwrap();
+        let path = folder.path().join("a/test.txt");
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        // The version's own metadata is restored
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        }
+        let versions = local.versions("a/test.txt").await.unwrap();
+        assert_eq!(std::fs::read(&versions[0].path).unwrap(), b"fourth");
+
+        let reader = Cursor::new(b"x".to_vec());
+        let result =
+            crate::Backend::upload(&local, Box::new(reader), 1, ".versions/x".into()).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_checksums() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        let source = folder.path().join("source.txt");
+        std::fs::write(&source, b"Hello, world!").unwrap();
+        let sha256 = "315f5bdb76d078c43b8ac0064e4a0164612b1fce77c869345bfc94c75894edd3";
+
+        for layout in [
+            super::ChecksumLayout::Sidecar,
+            super::ChecksumLayout::Manifest,
+        ] {
+            let data = folder.path().join(format!("{:?}", layout));
+            let checksums = super::Checksums::new(layout);
+            #[cfg(feature = "blake3")]
+            let checksums = checksums.with_blake3(true);
+            let local = Local::new(data.clone()).with_checksums(checksums);
+            let reader = Cursor::new(b"Hello, world!".to_vec());
+            crate::Backend::upload(&local, Box::new(reader), 13, "a.txt".into())
+                .await
+                .unwrap();
+            crate::Backend::upload_file(&local, source.clone(), "b.txt".into())
+                .await
+                .unwrap();
+            local.verify("a.txt").await.unwrap();
+            local.verify("b.txt").await.unwrap();
+
+            let checksum_file = match layout {
+                super::ChecksumLayout::Sidecar => data.join("a.txt.sha256"),
+                super::ChecksumLayout::Manifest => data.join("SHA256SUMS"),
+            };
+            let content = std::fs::read_to_string(checksum_file).unwrap();
+            assert!(content.contains(&format!("{}  a.txt\n", sha256)));
+
+            std::fs::write(data.join("b.txt"), b"Hello, world?").unwrap();
+            let result = local.verify("b.txt").await;
+            assert!(matches!(result, Err(super::Error::ChecksumMismatch { .. })));
+
+            let reserved = match layout {
+                super::ChecksumLayout::Sidecar => "a.txt.sha256",
+                super::ChecksumLayout::Manifest => "SHA256SUMS",
+            };
+            let reader = Cursor::new(b"x".to_vec());
+            let result = crate::Backend::upload(&local, Box::new(reader), 1, reserved.into()).await;
+            assert!(result
+                .unwrap_err()
+                .to_string()
+                .contains("kept for checksum files"));
+
+            if layout == super::ChecksumLayout::Sidecar {
+                // Names of checksum files without a stored file are free
+                let reader = Cursor::new(b"x".to_vec());
+                crate::Backend::upload(&local, Box::new(reader), 1, "c.txt.sha256".into())
+                    .await
+                    .unwrap();
+                // and aren't replaced by the checksum file of another upload
+                let reader = Cursor::new(b"x".to_vec());
+                let result =
+                    crate::Backend::upload(&local, Box::new(reader), 1, "c.txt".into()).await;
+                assert!(result.is_err());
+                assert_eq!(std::fs::read(data.join("c.txt.sha256")).unwrap(), b"x");
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_sparse_files() {
+        use std::os::unix::fs::{FileExt as _, MetadataExt as _};
+
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().join("data")).with_sparse_files(true);
+        let size = 16 * 1024 * 1024;
+        let mut content = vec![0; size];
+        content[size / 2..size / 2 + 5].copy_from_slice(b"Hello");
+
+        // Zeros in a stream and the holes of a source file
+        let source = folder.path().join("source.img");
+        let file = std::fs::File::create(&source).unwrap();
+        file.set_len(size as u64).unwrap();
+        file.write_all_at(b"Hello", size as u64 / 2).unwrap();
+        let reader = Cursor::new(content.clone());
+        crate::Backend::upload(&local, Box::new(reader), size as u64, "stream.img".into())
+            .await
+            .unwrap();
+        crate::Backend::upload_file(&local, source, "file.img".into())
+            .await
+            .unwrap();
+
+        for name in ["stream.img", "file.img"] {
+            let path = folder.path().join("data").join(name);
+            assert!(std::fs::read(&path).unwrap() == content);
+            let allocated = std::fs::metadata(&path).unwrap().blocks() * 512;
+            assert!(allocated < 1024 * 1024, "{}: {} bytes", name, allocated);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_modes() {
+        use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+
+        let folder = temp_dir::TempDir::new().unwrap();
+        let gid = std::fs::metadata(folder.path()).unwrap().gid();
+        let local = Local::new(folder.path().to_path_buf())
+            .with_file_mode(0o660)
+            .with_dir_mode(0o2770)
+            .with_group(gid)
+            .with_sync(super::SyncPolicy::File);
+
+        let metadata = crate::Metadata {
+            permissions: Some(0o600),
+            ..Default::default()
+        };
+        crate::Backend::upload_with_metadata(
+            &local,
+            Box::new(Cursor::new(b"Hello".to_vec())),
+            5,
+            "a/b/test.txt".into(),
+            metadata,
+        )
+        .await
+        .unwrap();
+
+        for (path, mode) in [("a", 0o2770), ("a/b", 0o2770), ("a/b/test.txt", 0o660)] {
+            let metadata = std::fs::metadata(folder.path().join(path)).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, mode, "{}", path);
+            assert_eq!(metadata.gid(), gid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let folder = temp_dir::TempDir::new().unwrap();
+        std::fs::write(folder.path().join("existing.bin"), [1; 600]).unwrap();
+        let local = Local::new(folder.path().to_path_buf())
+            .with_limits(super::Limits::new().max_total_size(1000).max_file_size(500));
+        let upload = |size: usize, announced: u64, path: &'static str| {
+            let reader = Cursor::new(vec![1; size]);
+            crate::Backend::upload(&local, Box::new(reader), announced, path.into())
+        };
+
+        assert!(upload(600, 600, "large.bin").await.is_err());
+        // More data than announced is cut off at the limit
+        assert!(upload(600, 100, "lying.bin").await.is_err());
+        assert!(!folder.path().join("lying.bin").exists());
+
+        upload(300, 300, "a.bin").await.unwrap();
+        assert!(upload(200, 200, "b.bin").await.is_err());
+        // Replacing a file frees its space
+        upload(100, 100, "a.bin").await.unwrap();
+        upload(200, 200, "b.bin").await.unwrap();
+
+        async fn upload_bytes(
+            local: &Local,
+            byte: u8,
+            size: usize,
+            path: &str,
+        ) -> Result<(), Box<dyn snafu::Error>> {
+            let reader = Cursor::new(vec![byte; size]);
+            crate::Backend::upload(local, Box::new(reader), size as u64, path.into()).await
+        }
+        for mode in [
+            super::ContentAddressing::Hardlinks,
+            super::ContentAddressing::Pointers,
+        ] {
+            let folder = temp_dir::TempDir::new().unwrap();
+            let local = || {
+                Local::new(folder.path().to_path_buf())
+                    .with_limits(super::Limits::new().max_total_size(13_000))
+                    .with_content_addressing(mode)
+            };
+            let first = local();
+
+            // Identical uploads take the space once, only while written twice
+            upload_bytes(&first, 1, 6000, "a.bin").await.unwrap();
+            upload_bytes(&first, 1, 6000, "b.bin").await.unwrap();
+            upload_bytes(&first, 2, 6000, "c.bin").await.unwrap();
+            assert!(upload_bytes(&first, 3, 1200, "d.bin").await.is_err());
+            // Measuring the folder counts hardlinks once too
+            upload_bytes(&local(), 3, 200, "d.bin").await.unwrap();
+
+            upload_bytes(&first, 4, 100, "a.bin").await.unwrap();
+            upload_bytes(&first, 4, 100, "b.bin").await.unwrap();
+            let collection = first.collect_garbage().await.unwrap();
+            assert_eq!(collection.freed, 6000);
+            upload_bytes(&first, 5, 6000, "e.bin").await.unwrap();
+        }
+
+        // Pruned versions free their space, the current file and the one
+        // version kept leave room for one more upload
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = Local::new(folder.path().to_path_buf())
+            .with_limits(super::Limits::new().max_total_size(130))
+            .with_versioning(super::Versioning::new().keep_last(1));
+        for _ in 0..10 {
+            let reader = Cursor::new(vec![1; 40]);
+            crate::Backend::upload(&local, Box::new(reader), 40, "a.bin".into())
+                .await
+                .unwrap();
+        }
+        assert_eq!(local.versions("a.bin").await.unwrap().len(), 1);
+
+        // Checksum and pointer files are counted like the uploads
+        let folder = temp_dir::TempDir::new().unwrap();
+        let local = || {
+            Local::new(folder.path().to_path_buf())
+                .with_limits(super::Limits::new().max_total_size(100_000))
+                .with_checksums(super::Checksums::new(super::ChecksumLayout::Sidecar))
+                .with_content_addressing(super::ContentAddressing::Pointers)
+        };
+        let first = local();
+        for (byte, path) in [(1, "a.bin"), (1, "b.bin"), (2, "a.bin")] {
+            upload_bytes(&first, byte, 1000, path).await.unwrap();
+        }
+        let measured = local();
+        drop(
+            measured
+                .reserve(std::path::Path::new("x"), 0)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(
+            *first.usage.lock().unwrap(),
+            *measured.usage.lock().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_addressing() {
+        use super::ContentAddressing;
+
+        for mode in [ContentAddressing::Hardlinks, ContentAddressing::Pointers] {
+            let folder = temp_dir::TempDir::new().unwrap();
+            let local = Local::new(folder.path().to_path_buf()).with_content_addressing(mode);
+            let upload = |content: &'static [u8], path: &'static str| {
+                let reader = Cursor::new(content.to_vec());
+                crate::Backend::upload(&local, Box::new(reader), content.len() as u64, path.into())
+            };
+
+            upload(b"same", "a.bin").await.unwrap();
+            upload(b"same", "b/b.bin").await.unwrap();
+            let objects = || walk(&folder.path().join(".objects"));
+            assert_eq!(objects().len(), 1);
+            let object = local.resolve("b/b.bin").await.unwrap();
+            assert_eq!(std::fs::read(&object).unwrap(), b"same");
+            match mode {
+                ContentAddressing::Pointers => {
+                    assert_eq!(object, objects()[0]);
+                    let pointer = std::fs::read_to_string(folder.path().join("a.bin")).unwrap();
+                    assert!(pointer.starts_with("sha256:"));
+                }
+                #[cfg(unix)]
+                ContentAddressing::Hardlinks => {
+                    use std::os::unix::fs::MetadataExt as _;
+                    let ino = |path| std::fs::metadata(path).unwrap().ino();
+                    assert_eq!(ino(object), ino(objects()[0].clone()));
+                }
+                #[cfg(not(unix))]
+                ContentAddressing::Hardlinks => {}
+            }
+
+            // Files which aren't objects are left alone
+            let foreign = [".objects/images/photo.jpg", ".objects/ab/notes.txt"];
+            for path in foreign {
+                let path = folder.path().join(path);
+                std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+                std::fs::write(path, b"mine").unwrap();
+            }
+
+            upload(b"other", "a.bin").await.unwrap();
+            assert_eq!(local.collect_garbage().await.unwrap().removed, 0);
+            upload(b"other", "b/b.bin").await.unwrap();
+            let collection = local.collect_garbage().await.unwrap();
+            assert_eq!(
+                collection,
+                super::GarbageCollection {
+                    removed: 1,
+                    freed: 4
+                }
+            );
+            assert_eq!(objects().len(), 1 + foreign.len());
+            assert!(foreign.iter().all(|path| folder.path().join(path).exists()));
+            assert!(upload(b"x", ".objects/x").await.is_err());
+            upload(b"x", "objects/x").await.unwrap();
+        }
+    }
+
+    fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .flat_map(|entry| {
+                let path = entry.unwrap().path();
+                match path.is_dir() {
+                    true => walk(&path),
+                    false => vec![path],
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use snafu::ResultExt;
+use tracing::debug;
+
+use super::{
+    copy,
+    fs::{create_dirs, finish_file},
+    temp_path, ContentAddressing, CopyDataSnafu, Error, Local, VersionSnafu,
+};
+use crate::{path::RelativePath, Metadata};
+
+/// The folder below the folder of the backend previous versions are kept in.
+pub(super) const VERSIONS_DIR: &str = ".versions";
+
+/// How long previous versions of a file are kept.
+/// Without limits every version is kept.
+#[derive(Debug, Clone, Default)]
+pub struct Versioning {
+    keep_last: Option<usize>,
+    keep_for: Option<Duration>,
+}
+
+impl Versioning {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `count` previous versions of each file.
+    pub fn keep_last(mut self, count: usize) -> Self {
+        self.keep_last = Some(count);
+        self
+    }
+
+    /// Delete versions replaced longer than `duration` ago.
+    pub fn keep_for(mut self, duration: Duration) -> Self {
+        self.keep_for = Some(duration);
+        self
+    }
+}
+
+/// A previous version of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// Where the version is stored, `.versions/<path>/<timestamp>`.
+    pub path: PathBuf,
+    /// When the version was replaced.
+    pub replaced: SystemTime,
+    pub size: u64,
+}
+
+impl Local {
+    /// List the previous versions of a file, newest first.
+    /// path: The path the file was uploaded to.
+    pub async fn versions(&self, path: impl AsRef<Path>) -> Result<Vec<Version>, Error> {
+        let dir = self.versions_dir(&RelativePath::new(path)?);
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|_| VersionSnafu {
+                    msg: dir.to_string_lossy().to_string(),
+                })
+            }
+        };
+
+        let mut versions = Vec::new();
+        while let Some(entry) = entries.next_entry().await.with_context(|_| VersionSnafu {
+            msg: dir.to_string_lossy().to_string(),
+        })? {
+            let Some(replaced) = entry.file_name().to_str().and_then(parse_version_name) else {
+                continue;
+            };
+            let metadata = entry.metadata().await.with_context(|_| VersionSnafu {
+                msg: entry.path().to_string_lossy().to_string(),
+            })?;
+            if metadata.is_file() {
+                versions.push(Version {
+                    path: entry.path(),
+                    replaced,
+                    size: metadata.len(),
+                });
+            }
+        }
+        versions.sort_by_key(|version| std::cmp::Reverse(version.replaced));
+        Ok(versions)
+    }
+
+    /// Replace a file with one of its previous versions, with the times and
+    /// permissions it had.
+    /// The current file is kept as a version itself.
+    pub async fn restore(&self, path: impl AsRef<Path>, version: &Version) -> Result<(), Error> {
+        let relative = RelativePath::new(path.as_ref())?;
+        // Only restore from the versions of this file
+        if version.path.parent() != Some(self.versions_dir(&relative).as_path()) {
+            return Err(Error::UnknownVersion {
+                msg: version.path.to_string_lossy().to_string(),
+            });
+        }
+        let (path, _reservation) = self.prepare(path.as_ref(), version.size).await?;
+        let temp_path = temp_path(&path);
+        // Versions of pointer files point into the object store as well
+        let source = self.content_path(&version.path).await?;
+        // The times and permissions the file had before it was replaced
+        let metadata = tokio::fs::metadata(&version.path)
+            .await
+            .with_context(|_| VersionSnafu {
+                msg: version.path.to_string_lossy().to_string(),
+            })?;
+        let metadata = Metadata::from(&metadata);
+
+        let result = async {
+            let (from, to, sparse) = (source, temp_path.clone(), self.options.sparse);
+            let file = tokio::task::spawn_blocking(move || copy::copy_file(&from, &to, sparse))
+                .await
+                .map_err(std::io::Error::other)
+                .and_then(|result| result)
+                .with_context(|_| CopyDataSnafu {
+                    msg: temp_path.to_string_lossy().to_string(),
+                })?;
+            finish_file(file, &temp_path, &metadata, &self.options).await
+        }
+        .await;
+        self.store(result, &temp_path, &path, None).await
+    }
+
+    fn versions_dir(&self, relative: &RelativePath) -> PathBuf {
+        relative.to_path(self.folder.join(VERSIONS_DIR))
+    }
+
+    /// Keep the current content of `path` as a version before it is replaced,
+    /// then apply the retention policy.
+    pub(super) async fn keep_version(
+        &self,
+        path: &Path,
+        versioning: &Versioning,
+    ) -> Result<(), Error> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let msg = || path.to_string_lossy().to_string();
+        let relative = path
+            .strip_prefix(&self.folder)
+            .ok()
+            .and_then(|relative| RelativePath::new(relative).ok())
+            .ok_or_else(|| Error::UnknownVersion { msg: msg() })?;
+
+        let dir = self.versions_dir(&relative);
+        create_dirs(&dir, &self.options)
+            .await
+            .with_context(|_| VersionSnafu { msg: msg() })?;
+        let version = dir.join(version_name(SystemTime::now()));
+        // The file is renamed over afterwards, the link keeps the old content
+        if tokio::fs::hard_link(path, &version).await.is_err() {
+            tokio::fs::copy(path, &version)
+                .await
+                .with_context(|_| VersionSnafu { msg: msg() })?;
+        }
+        debug!("Kept version {:?}", version);
+
+        self.prune(&relative, versioning).await
+    }
+
+    /// Delete the versions outside the retention policy.
+    async fn prune(&self, relative: &RelativePath, versioning: &Versioning) -> Result<(), Error> {
+        let now = SystemTime::now();
+        let versions = self.versions(relative).await?;
+        for (i, version) in versions.iter().enumerate() {
+            let too_many = versioning.keep_last.is_some_and(|keep| i >= keep);
+            let too_old = versioning.keep_for.is_some_and(|keep_for| {
+                now.duration_since(version.replaced)
+                    .is_ok_and(|age| age > keep_for)
+            });
+            if too_many || too_old {
+                debug!("Deleting version {:?}", version.path);
+                tokio::fs::remove_file(&version.path)
+                    .await
+                    .with_context(|_| VersionSnafu {
+                        msg: version.path.to_string_lossy().to_string(),
+                    })?;
+                // Objects linked by versions are freed by garbage collection
+                if self.content_addressing != Some(ContentAddressing::Hardlinks) {
+                    self.account(0, version.size);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A name sorting in the order versions were replaced, e.g. `01729252800.123456789`.
+fn version_name(time: SystemTime) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{:011}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+fn parse_version_name(name: &str) -> Option<SystemTime> {
+    let (secs, nanos) = name.split_once('.')?;
+    let time = Duration::new(secs.parse().ok()?, nanos.parse().ok()?);
+    Some(UNIX_EPOCH + time)
+}
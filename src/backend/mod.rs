@@ -1,10 +1,37 @@
 mod local;
 
-pub use local::Local;
+/// Characters kept as is in a path segment of a URL, everything else is percent-encoded.
+#[cfg(any(feature = "onedrive", feature = "webdav"))]
+const URL_SEGMENT: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+pub use local::{
+    ChecksumLayout, Checksums as LocalChecksums, ContentAddressing, GarbageCollection,
+    Limits as LocalLimits, Local, SyncPolicy, Version as LocalVersion,
+    Versioning as LocalVersioning,
+};
 
 #[cfg(feature = "onedrive")]
 mod onedrive;
 #[cfg(feature = "onedrive")]
+pub use onedrive::delta::{
+    ChangeKind as OnedriveChangeKind, Delta as OnedriveDelta, DeltaItem as OnedriveDeltaItem,
+};
+#[cfg(feature = "onedrive")]
+pub use onedrive::name::{
+    validate_name as validate_onedrive_name, InvalidNameReason as OnedriveInvalidNameReason,
+    Sanitizer as OnedriveSanitizer,
+};
+#[cfg(feature = "onedrive")]
+pub use onedrive::share::{
+    InviteOptions as OnedriveInviteOptions, LinkOptions as OnedriveLinkOptions,
+    LinkScope as OnedriveLinkScope, LinkType as OnedriveLinkType, Permission as OnedrivePermission,
+    Role as OnedriveRole, SharingLink as OnedriveSharingLink,
+};
+#[cfg(feature = "onedrive")]
 pub use onedrive::ApiType as OnedriveApiType;
 #[cfg(feature = "onedrive")]
 pub use onedrive::Onedrive;
@@ -14,4 +41,7 @@ mod webdav;
 #[cfg(feature = "webdav")]
 pub use reqwest_dav::Auth as WebdavAuth;
 #[cfg(feature = "webdav")]
-pub use webdav::Webdav;
+pub use webdav::{
+    Capabilities as WebdavCapabilities, Checksum as WebdavChecksum, NextcloudChunking,
+    PartialUpdate as WebdavPartialUpdate, Probe as WebdavProbe, Webdav, WebdavBuilder,
+};
@@ -209,7 +209,8 @@ impl OnedriveInner {
             refresh_token: ArcSwap::from_pointee(refresh_token),
             expires_at: AtomicU64::new(expires_at),
             api_type,
-            folder: path.as_ref().to_path_buf(),
+            folder: super::name::normalize_path(path.as_ref()),
+            items: Default::default(),
         }
     }
 }
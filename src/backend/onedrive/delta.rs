@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use super::{DeltaSnafu, Error, OnedriveInner};
+
+/// How an item changed since the previous delta token.
+///
+/// Graph does not report creations explicitly, an item is considered
+/// created when its creation time equals its last modification time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// A changed item below the folder of the backend.
+#[derive(Debug, Clone)]
+pub struct DeltaItem {
+    pub id: String,
+    pub kind: ChangeKind,
+    /// The item path, relative to the folder of the backend.
+    /// `None` if the parents of the item weren't enumerated by this process,
+    /// e.g. when continuing from the token of a previous run. Such items may
+    /// lie outside the folder, match them by `id`.
+    pub path: Option<PathBuf>,
+    pub is_folder: bool,
+    pub size: Option<u64>,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub etag: Option<String>,
+}
+
+/// The result of a delta query.
+#[derive(Debug, Clone)]
+pub struct Delta {
+    pub items: Vec<DeltaItem>,
+    /// Pass this token to the next [`crate::backend::Onedrive::delta`] call
+    /// to only receive changes made after this query.
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeltaPage {
+    value: Vec<DriveItem>,
+    #[serde(rename = "@odata.nextLink")]
+    next_link: Option<String>,
+    #[serde(rename = "@odata.deltaLink")]
+    delta_link: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveItem {
+    id: String,
+    name: Option<String>,
+    size: Option<u64>,
+    e_tag: Option<String>,
+    created_date_time: Option<DateTime<Utc>>,
+    last_modified_date_time: Option<DateTime<Utc>>,
+    parent_reference: Option<ParentReference>,
+    folder: Option<serde_json::Value>,
+    deleted: Option<serde_json::Value>,
+    root: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ParentReference {
+    id: Option<String>,
+    /// Left out of delta responses, except by some personal accounts.
+    path: Option<String>,
+}
+
+/// The parent and name of every item seen in delta responses, to build the
+/// paths Graph leaves out. Parents are returned before their children.
+#[derive(Debug, Default)]
+pub(super) struct ItemTree {
+    items: HashMap<String, TreeNode>,
+}
+
+#[derive(Debug)]
+struct TreeNode {
+    /// `None` for the drive root.
+    parent: Option<String>,
+    name: String,
+}
+
+impl ItemTree {
+    fn update(&mut self, item: &DriveItem) {
+        let parent = item.parent_reference.as_ref().and_then(|p| p.id.clone());
+        let node = match (item.root.is_some(), parent, &item.name) {
+            (true, _, _) => TreeNode {
+                parent: None,
+                name: String::new(),
+            },
+            (false, Some(parent), Some(name)) => TreeNode {
+                parent: Some(parent),
+                name: name.clone(),
+            },
+            _ => return,
+        };
+        self.items.insert(item.id.clone(), node);
+    }
+
+    /// The absolute path of the item `id`, if all its parents are known.
+    fn path(&self, id: &str) -> Option<PathBuf> {
+        let mut names = Vec::new();
+        let mut node = self.items.get(id)?;
+        while let Some(parent) = &node.parent {
+            // A cycle can only come from a broken response
+            if names.len() > self.items.len() {
+                return None;
+            }
+            names.push(node.name.as_str());
+            node = self.items.get(parent)?;
+        }
+        Some(
+            names
+                .iter()
+                .rev()
+                .fold(PathBuf::from("/"), |path, name| path.join(name)),
+        )
+    }
+}
+
+impl OnedriveInner {
+    pub async fn delta(&self, token: Option<&str>) -> Result<Delta, Error> {
+        // OneDrive for Business and SharePoint only track the drive root,
+        // items outside the folder are filtered by their path
+        let mut url = match token {
+            Some(token) => token.to_string(),
+            None => format!("{}/delta", self.api_type.item_url(Path::new("/"))),
+        };
+
+        let mut items = Vec::new();
+        loop {
+            let response = reqwest::Client::new()
+                .get(&url)
+                .header("Authorization", format!("Bearer {}", self.access_token))
+                .send()
+                .await
+                .context(DeltaSnafu)?;
+
+            let page = match response.status() {
+                reqwest::StatusCode::OK => {
+                    response.json::<DeltaPage>().await.context(DeltaSnafu)?
+                }
+                reqwest::StatusCode::GONE => return Err(Error::DeltaResync),
+                _ => {
+                    return Err(Error::Delta {
+                        source: response.error_for_status().unwrap_err(),
+                    })
+                }
+            };
+
+            let mut tree = self.items.lock().unwrap_or_else(|e| e.into_inner());
+            items.extend(
+                page.value
+                    .into_iter()
+                    .filter_map(|item| delta_item(&self.folder, &mut tree, item)),
+            );
+            drop(tree);
+
+            match (page.next_link, page.delta_link) {
+                (Some(next_link), _) => url = next_link,
+                (None, Some(delta_link)) => {
+                    return Ok(Delta {
+                        items,
+                        token: delta_link,
+                    })
+                }
+                (None, None) => {
+                    return Err(Error::Parsing {
+                        context: "delta response without nextLink or deltaLink".to_string(),
+                    })
+                }
+            }
+        }
+    }
+}
+
+fn delta_item(folder: &Path, tree: &mut ItemTree, item: DriveItem) -> Option<DeltaItem> {
+    if item.deleted.is_none() {
+        tree.update(&item);
+    }
+    let path = tree.path(&item.id).or_else(|| {
+        // parentReference.path looks like `/drive/root:/folder/sub`
+        let parent = item.parent_reference.as_ref()?.path.as_ref()?;
+        let parent = parent.split_once("root:").map_or("", |(_, p)| p);
+        let parent = Path::new("/").join(parent.trim_start_matches('/'));
+        Some(parent.join(item.name.as_ref()?))
+    });
+    if item.deleted.is_some() {
+        tree.items.remove(&item.id);
+    }
+
+    let path = match path {
+        Some(path) => {
+            // The folder may be configured relative to the drive root
+            let folder = Path::new("/").join(folder);
+            let relative = path.strip_prefix(folder).ok()?.to_path_buf();
+            // The folder of the backend itself is part of the result
+            if relative.as_os_str().is_empty() {
+                return None;
+            }
+            Some(relative)
+        }
+        None => None,
+    };
+
+    let kind = if item.deleted.is_some() {
+        ChangeKind::Deleted
+    } else if item.created_date_time.is_some()
+        && item.created_date_time == item.last_modified_date_time
+    {
+        ChangeKind::Created
+    } else {
+        ChangeKind::Modified
+    };
+
+    Some(DeltaItem {
+        id: item.id,
+        kind,
+        path,
+        is_folder: item.folder.is_some(),
+        size: item.size,
+        last_modified: item.last_modified_date_time,
+        etag: item.e_tag,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn relative_delta_path() {
+        let item = |parent: &str, name: &str, deleted: bool| DriveItem {
+            id: name.to_string(),
+            name: Some(name.to_string()),
+            size: Some(1),
+            e_tag: None,
+            created_date_time: None,
+            last_modified_date_time: None,
+            parent_reference: Some(ParentReference {
+                id: None,
+                path: Some(parent.to_string()),
+            }),
+            folder: None,
+            deleted: deleted.then(|| serde_json::json!({})),
+            root: None,
+        };
+        let folder = Path::new("/backup");
+        let delta_item = |folder, item| delta_item(folder, &mut ItemTree::default(), item);
+
+        let changed = delta_item(folder, item("/drive/root:/backup/2024", "a.txt", false)).unwrap();
+        assert_eq!(changed.path.unwrap(), Path::new("2024/a.txt"));
+        assert_eq!(changed.kind, ChangeKind::Modified);
+
+        let deleted = delta_item(folder, item("/drive/root:/backup", "b.txt", true)).unwrap();
+        assert_eq!(deleted.path.unwrap(), Path::new("b.txt"));
+        assert_eq!(deleted.kind, ChangeKind::Deleted);
+
+        assert!(delta_item(folder, item("/drive/root:", "backup", false)).is_none());
+        assert!(delta_item(folder, item("/drive/root:/other", "c.txt", false)).is_none());
+
+        let relative = delta_item(
+            Path::new("backup"),
+            item("/drive/root:/backup", "d.txt", false),
+        );
+        assert_eq!(relative.unwrap().path.unwrap(), Path::new("d.txt"));
+    }
+
+    /// Pages shaped like Graph's, which leave out `parentReference.path`.
+    #[test]
+    fn delta_pages() {
+        let first = r#"{
+            "@odata.nextLink": "https://graph.microsoft.com/v1.0/me/drive/root/delta?token=1",
+            "value": [
+                {"id": "R", "name": "root", "root": {}, "folder": {"childCount": 2},
+                 "parentReference": {"driveId": "d", "driveType": "personal"}},
+                {"id": "B", "name": "backup", "folder": {"childCount": 1},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "R"}},
+                {"id": "Y", "name": "2024", "folder": {"childCount": 1},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "B"}},
+                {"id": "A", "name": "a.txt", "size": 5, "file": {},
+                 "createdDateTime": "2024-01-01T00:00:00Z",
+                 "lastModifiedDateTime": "2024-01-01T00:00:00Z",
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "Y"}},
+                {"id": "O", "name": "other", "folder": {"childCount": 1},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "R"}},
+                {"id": "C", "name": "c.txt", "size": 5, "file": {},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "O"}}
+            ]
+        }"#;
+        let second = r#"{
+            "@odata.deltaLink": "https://graph.microsoft.com/v1.0/me/drive/root/delta?token=2",
+            "value": [
+                {"id": "A", "deleted": {"state": "deleted"},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "Y"}},
+                {"id": "U", "name": "u.txt", "size": 5, "file": {},
+                 "parentReference": {"driveId": "d", "driveType": "personal", "id": "unknown"}}
+            ]
+        }"#;
+
+        let mut tree = ItemTree::default();
+        let mut items = Vec::new();
+        for page in [first, second] {
+            let page = serde_json::from_str::<DeltaPage>(page).unwrap();
+            items.extend(
+                page.value
+                    .into_iter()
+                    .filter_map(|item| delta_item(Path::new("/backup"), &mut tree, item)),
+            );
+        }
+
+        let items = items
+            .iter()
+            .map(|item| (item.id.as_str(), item.kind, item.path.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                ("Y", ChangeKind::Modified, Some(Path::new("2024"))),
+                ("A", ChangeKind::Created, Some(Path::new("2024/a.txt"))),
+                ("A", ChangeKind::Deleted, Some(Path::new("2024/a.txt"))),
+                // The parent wasn't enumerated, the item is kept without a path
+                ("U", ChangeKind::Modified, None),
+            ]
+        );
+    }
+}
@@ -10,9 +10,13 @@ use serde::{Deserialize, Serialize};
 use snafu::Snafu;
 use tracing::{debug, warn};
 
-use crate::{AsyncBufReadSeek, Backend};
+use crate::{AsyncBufReadSeek, Backend, Quota};
 
 pub mod auth;
+pub mod delta;
+pub mod name;
+pub mod quota;
+pub mod share;
 pub mod upload;
 
 struct OnedriveInner {
@@ -22,6 +26,8 @@ struct OnedriveInner {
     expires_at: AtomicU64,
     api_type: ApiType,
     folder: PathBuf,
+    /// The items seen by [`Onedrive::delta`]
+    items: std::sync::Mutex<delta::ItemTree>,
 }
 
 impl Debug for OnedriveInner {
@@ -37,6 +43,8 @@ impl Debug for OnedriveInner {
 pub struct Onedrive {
     inner: Arc<OnedriveInner>,
     refresh_handle: tokio::task::JoinHandle<()>,
+    check_quota: bool,
+    sanitizer: Option<name::Sanitizer>,
 }
 
 impl Drop for Onedrive {
@@ -54,8 +62,26 @@ impl Backend for Onedrive {
         path: PathBuf,
     ) -> Result<(), Box<dyn snafu::Error>> {
         debug!("Uploading file to onedrive: {:?}", &path);
+        let path = self.sanitize(&path);
+        self.inner.validate_path(&path)?;
+        if self.check_quota {
+            if let Some(quota) = self.inner.quota().await? {
+                if quota.remaining < size {
+                    return Err(Error::InsufficientStorage {
+                        file: path.to_string_lossy().to_string(),
+                        size,
+                        remaining: quota.remaining,
+                    }
+                    .into());
+                }
+            }
+        }
         self.inner.upload(reader, size, path).await
     }
+
+    async fn quota(&self) -> Result<Option<Quota>, Box<dyn snafu::Error>> {
+        Ok(self.inner.quota().await?)
+    }
 }
 
 impl Onedrive {
@@ -63,6 +89,64 @@ impl Onedrive {
         self.inner.refresh_token.load().to_string()
     }
 
+    /// Check the remaining space of the drive before starting an upload.
+    pub fn with_quota_check(mut self, check_quota: bool) -> Self {
+        self.check_quota = check_quota;
+        self
+    }
+
+    /// Rewrite names OneDrive rejects before uploading, instead of failing.
+    /// Paths returned by [`Onedrive::delta`] are restored with the same mapping.
+    pub fn with_sanitizer(mut self, sanitizer: name::Sanitizer) -> Self {
+        self.sanitizer = Some(sanitizer);
+        self
+    }
+
+    fn sanitize(&self, path: &Path) -> PathBuf {
+        match &self.sanitizer {
+            Some(sanitizer) => sanitizer.sanitize_path(path),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Create a sharing link for an uploaded item.
+    /// path: The item path, relative to the folder of the backend.
+    pub async fn create_link(
+        &self,
+        path: impl AsRef<Path>,
+        options: share::LinkOptions,
+    ) -> Result<share::Permission, Error> {
+        self.inner
+            .create_link(&self.sanitize(path.as_ref()), &options)
+            .await
+    }
+
+    /// Grant access to an uploaded item by email.
+    /// path: The item path, relative to the folder of the backend.
+    pub async fn invite(
+        &self,
+        path: impl AsRef<Path>,
+        options: share::InviteOptions,
+    ) -> Result<Vec<share::Permission>, Error> {
+        self.inner
+            .invite(&self.sanitize(path.as_ref()), &options)
+            .await
+    }
+
+    /// List the changes below the folder of the backend.
+    /// Changes are tracked for the whole drive, as OneDrive for Business only
+    /// supports it on the root, so the first query enumerates the whole drive.
+    /// token: The token of a previous delta query, `None` to enumerate every item.
+    pub async fn delta(&self, token: Option<&str>) -> Result<delta::Delta, Error> {
+        let mut delta = self.inner.delta(token).await?;
+        if let Some(sanitizer) = &self.sanitizer {
+            for item in delta.items.iter_mut() {
+                item.path = item.path.as_deref().map(|p| sanitizer.restore_path(p));
+            }
+        }
+        Ok(delta)
+    }
+
     pub async fn new_with_code(
         client_id: impl Into<String>,
         client_secret: impl Into<String>,
@@ -80,6 +164,8 @@ impl Onedrive {
         Ok(Self {
             inner,
             refresh_handle,
+            check_quota: false,
+            sanitizer: None,
         })
     }
 
@@ -107,6 +193,8 @@ impl Onedrive {
         Ok(Self {
             inner,
             refresh_handle,
+            check_quota: false,
+            sanitizer: None,
         })
     }
 }
@@ -164,7 +252,6 @@ impl ApiType {
         }
     }
 
-    #[allow(dead_code)]
     fn get_graph_url(&self) -> &'static str {
         match self {
             ApiType::Common => "https://graph.microsoft.com/v1.0",
@@ -173,6 +260,26 @@ impl ApiType {
             ApiType::ChinaApi => "https://microsoftgraph.chinacloudapi.cn/v1.0",
         }
     }
+
+    /// The URL of the item at an absolute `path`.
+    fn item_url(&self, path: &Path) -> String {
+        let encoded = name::encode_path(path);
+        if encoded.is_empty() {
+            format!("{}/me/drive/root", self.get_graph_url())
+        } else {
+            format!("{}/me/drive/root:{}", self.get_graph_url(), encoded)
+        }
+    }
+
+    /// The URL of the item `name` in the folder `parent_id`, without the trailing action.
+    fn child_url(&self, parent_id: &str, name: &str) -> String {
+        format!(
+            "{}/me/drive/items/{}:/{}:",
+            self.get_graph_url(),
+            parent_id,
+            name::encode_name(name)
+        )
+    }
 }
 
 #[derive(Debug, Snafu)]
@@ -198,6 +305,19 @@ pub enum Error {
     #[snafu(display("Invalid Path: {}", path))]
     InvalidPath { path: String },
 
+    #[snafu(transparent)]
+    Path { source: crate::path::Error },
+
+    #[snafu(display("Invalid name {} in path {}: {}", name, path, reason))]
+    InvalidName {
+        path: String,
+        name: String,
+        reason: name::InvalidNameReason,
+    },
+
+    #[snafu(display("Path {} is {} characters long, the maximum is 400", path, len))]
+    PathTooLong { path: String, len: usize },
+
     #[snafu(display("Failed to create directory for path: {}, error: {}", path, source))]
     CreateDir {
         source: reqwest::Error,
@@ -221,4 +341,37 @@ pub enum Error {
 
     #[snafu(display("Failed to upload file: {}", source))]
     UploadFile { source: reqwest::Error },
+
+    #[snafu(display("Failed to get item for path: {}, error: {}", path, source))]
+    GetItem {
+        source: reqwest::Error,
+        path: String,
+    },
+
+    #[snafu(display("Item not found: {}", path))]
+    ItemNotFound { path: String },
+
+    #[snafu(display("Failed to create sharing link: {}", source))]
+    CreateLink { source: reqwest::Error },
+
+    #[snafu(display("Failed to invite recipients: {}", source))]
+    Invite { source: reqwest::Error },
+
+    #[snafu(display("Failed to query changes: {}", source))]
+    Delta { source: reqwest::Error },
+
+    #[snafu(display("The delta token is expired, a full resync is required"))]
+    DeltaResync,
+
+    #[snafu(display("Failed to get drive quota: {}", source))]
+    Quota { source: reqwest::Error },
+
+    #[snafu(display(
+        "Not enough space for {file}: {size} bytes required, {remaining} bytes remaining"
+    ))]
+    InsufficientStorage {
+        file: String,
+        size: u64,
+        remaining: u64,
+    },
 }
@@ -0,0 +1,507 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use percent_encoding::utf8_percent_encode;
+use sha2::{Digest as _, Sha256};
+use unicode_normalization::UnicodeNormalization as _;
+
+use super::{Error, OnedriveInner};
+use crate::{backend::URL_SEGMENT, hex, path::RelativePath};
+
+/// The maximum length of a decoded path, including the folder of the backend.
+const MAX_PATH_LENGTH: usize = 400;
+/// The maximum length of a single file or folder name.
+const MAX_NAME_LENGTH: usize = 255;
+
+const INVALID_CHARS: [char; 9] = ['"', '*', ':', '<', '>', '?', '/', '\\', '|'];
+const RESERVED_NAMES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+/// Why OneDrive or SharePoint rejects a file or folder name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidNameReason {
+    Empty,
+    InvalidChar(char),
+    LeadingSpace,
+    TrailingSpace,
+    TrailingDot,
+    Reserved,
+    TooLong(usize),
+}
+
+impl Display for InvalidNameReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InvalidNameReason::Empty => write!(f, "the name is empty"),
+            InvalidNameReason::InvalidChar(c) => write!(f, "the name contains '{}'", c),
+            InvalidNameReason::LeadingSpace => write!(f, "the name starts with a space"),
+            InvalidNameReason::TrailingSpace => write!(f, "the name ends with a space"),
+            InvalidNameReason::TrailingDot => write!(f, "the name ends with a dot"),
+            InvalidNameReason::Reserved => write!(f, "the name is reserved"),
+            InvalidNameReason::TooLong(len) => write!(
+                f,
+                "the name is {} characters long, the maximum is {}",
+                len, MAX_NAME_LENGTH
+            ),
+        }
+    }
+}
+
+/// Check a single file or folder name against the OneDrive and SharePoint restrictions.
+pub fn validate_name(name: &str) -> Result<(), InvalidNameReason> {
+    if name.is_empty() {
+        return Err(InvalidNameReason::Empty);
+    }
+    if let Some(c) = name.chars().find(|c| INVALID_CHARS.contains(c)) {
+        return Err(InvalidNameReason::InvalidChar(c));
+    }
+    if name.starts_with(' ') {
+        return Err(InvalidNameReason::LeadingSpace);
+    }
+    if name.ends_with(' ') {
+        return Err(InvalidNameReason::TrailingSpace);
+    }
+    if name.ends_with('.') {
+        return Err(InvalidNameReason::TrailingDot);
+    }
+    if is_reserved(name) {
+        return Err(InvalidNameReason::Reserved);
+    }
+    let len = name.chars().count();
+    if len > MAX_NAME_LENGTH {
+        return Err(InvalidNameReason::TooLong(len));
+    }
+    Ok(())
+}
+
+fn is_reserved(name: &str) -> bool {
+    is_reserved_name(name) || name.to_lowercase().contains("_vti_")
+}
+
+/// Whether the whole name is reserved, unlike `_vti_` anywhere in it.
+fn is_reserved_name(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    lower == ".lock"
+        || lower == "desktop.ini"
+        || lower.starts_with("~$")
+        || is_reserved_device(name)
+}
+
+/// Starts a replaced character in sanitized names, and is doubled where the
+/// original name contains it.
+const ESCAPE: char = '\u{FF3E}';
+
+/// A reversible mapping from names OneDrive rejects to names it accepts.
+///
+/// Every invalid character, a leading or trailing space, a trailing dot, the first
+/// character of a reserved name (`CON`, `desktop.ini`, `.lock`, `~$…`) and the `_`
+/// of `_vti_` are replaced with `＾` followed by their full width counterpart,
+/// e.g. `a?.txt` becomes `a＾？.txt`. A `＾` in the original name is doubled.
+/// Names created on OneDrive, e.g. Japanese names containing `？`, are left alone
+/// by [`Sanitizer::restore`] unless they contain `＾`.
+///
+/// Names over 255 characters are shortened, keeping the extension and adding a
+/// hash of the original name. Those can't be restored from the name alone, see
+/// [`Sanitizer::shortened`].
+#[derive(Debug, Clone)]
+pub struct Sanitizer {
+    mapping: Vec<(char, char)>,
+    space: char,
+    dot: char,
+    /// Shortened names to the names they were produced from, shared between clones.
+    shortened: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Self {
+        Self {
+            mapping: INVALID_CHARS
+                .iter()
+                .filter(|c| **c != '/')
+                .map(|c| (*c, to_full_width(*c)))
+                .collect(),
+            space: '\u{3000}',
+            dot: '\u{FF0E}',
+            shortened: Arc::default(),
+        }
+    }
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace `from` with `to`, overriding the default replacement.
+    /// Each replacement should be used for a single character only.
+    pub fn map(mut self, from: char, to: char) -> Self {
+        self.mapping.retain(|(f, _)| *f != from);
+        self.mapping.push((from, to));
+        self
+    }
+
+    /// The replacement of a leading or trailing space.
+    pub fn space(mut self, to: char) -> Self {
+        self.space = to;
+        self
+    }
+
+    /// The replacement of a trailing dot.
+    pub fn dot(mut self, to: char) -> Self {
+        self.dot = to;
+        self
+    }
+
+    /// Restore the shortened names of a previous run, see [`Sanitizer::shortened`].
+    pub fn with_shortened(self, names: impl IntoIterator<Item = (String, String)>) -> Self {
+        lock(&self.shortened).extend(names);
+        self
+    }
+
+    /// The names shortened so far with the names they were produced from, to be
+    /// persisted by callers restoring paths in a later run. Only names over 255
+    /// characters are kept.
+    pub fn shortened(&self) -> Vec<(String, String)> {
+        lock(&self.shortened)
+            .iter()
+            .map(|(shortened, name)| (shortened.clone(), name.clone()))
+            .collect()
+    }
+
+    pub fn sanitize(&self, name: &str) -> String {
+        let chars = name.chars().collect::<Vec<_>>();
+        // Positions of ASCII-lowercased names match the original
+        let lower = chars
+            .iter()
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+        let last = chars.len().saturating_sub(1);
+        let reserved = is_reserved_name(name);
+
+        let mut sanitized = String::with_capacity(name.len());
+        for (i, &c) in chars.iter().enumerate() {
+            let replace = INVALID_CHARS.contains(&c)
+                || (c == ' ' && (i == 0 || i == last))
+                || (c == '.' && i == last)
+                || (i == 0 && reserved)
+                || lower[i..].starts_with(&['_', 'v', 't', 'i', '_']);
+            if replace {
+                sanitized.push(ESCAPE);
+                sanitized.push(self.replacement(c));
+            } else if c == ESCAPE {
+                sanitized.push(ESCAPE);
+                sanitized.push(ESCAPE);
+            } else {
+                sanitized.push(c);
+            }
+        }
+        if sanitized.chars().count() <= MAX_NAME_LENGTH {
+            return sanitized;
+        }
+
+        // Distinct names sharing a prefix stay distinct
+        let hash = hex(&Sha256::digest(name.as_bytes()));
+        let marker = format!("~{}", &hash[..8]);
+        let chars = sanitized.chars().collect::<Vec<_>>();
+        // Keep a short extension, e.g. `.tar`
+        let extension = chars
+            .iter()
+            .rposition(|c| *c == '.')
+            .map_or(0, |dot| chars.len() - dot)
+            .min(MAX_NAME_LENGTH / 2);
+        let mut stem = chars[..MAX_NAME_LENGTH - extension - marker.len()].to_vec();
+        // Don't cut a replacement in half
+        let escapes = stem.iter().rev().take_while(|c| **c == ESCAPE).count();
+        if escapes % 2 == 1 {
+            stem.pop();
+        }
+        let shortened = stem
+            .into_iter()
+            .chain(marker.chars())
+            .chain(chars[chars.len() - extension..].iter().copied())
+            .collect::<String>();
+        lock(&self.shortened).insert(shortened.clone(), name.to_string());
+        shortened
+    }
+
+    /// The name `name` was sanitized from. Names without replacements are
+    /// returned as they are, shortened names only if they are known.
+    pub fn restore(&self, name: &str) -> String {
+        if let Some(original) = lock(&self.shortened).get(name) {
+            return original.clone();
+        }
+        let mut restored = String::with_capacity(name.len());
+        let mut chars = name.chars();
+        while let Some(c) = chars.next() {
+            if c != ESCAPE {
+                restored.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some(ESCAPE) | None => restored.push(ESCAPE),
+                Some(replacement) => restored.push(self.original(replacement)),
+            }
+        }
+        restored
+    }
+
+    fn replacement(&self, c: char) -> char {
+        match self.mapping.iter().find(|(from, _)| *from == c) {
+            Some((_, to)) => *to,
+            None if c == ' ' => self.space,
+            None if c == '.' => self.dot,
+            None => to_full_width(c),
+        }
+    }
+
+    fn original(&self, replacement: char) -> char {
+        match self.mapping.iter().find(|(_, to)| *to == replacement) {
+            Some((from, _)) => *from,
+            None if replacement == self.space => ' ',
+            None if replacement == self.dot => '.',
+            None => from_full_width(replacement),
+        }
+    }
+
+    pub fn sanitize_path(&self, path: &Path) -> PathBuf {
+        self.map_path(path, |name| self.sanitize(name))
+    }
+
+    pub fn restore_path(&self, path: &Path) -> PathBuf {
+        self.map_path(path, |name| self.restore(name))
+    }
+
+    fn map_path(&self, path: &Path, f: impl Fn(&str) -> String) -> PathBuf {
+        path.components()
+            .map(|component| match component {
+                Component::Normal(name) => f(&name.to_string_lossy()).into(),
+                other => other.as_os_str().to_os_string(),
+            })
+            .collect()
+    }
+}
+
+/// Device names are reserved with any extension, e.g. `CON.txt`.
+fn is_reserved_device(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).to_uppercase();
+    RESERVED_NAMES.contains(&stem.as_str())
+        || matches!(
+            stem.as_bytes(),
+            [b'C', b'O', b'M', b'0'..=b'9'] | [b'L', b'P', b'T', b'0'..=b'9']
+        )
+}
+
+fn to_full_width(c: char) -> char {
+    match c {
+        '!'..='~' => char::from_u32(c as u32 - 0x21 + 0xFF01).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn from_full_width(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFF01 + 0x21).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// The mapping is a plain cache, it can't be left inconsistent by a panic.
+fn lock(names: &Mutex<HashMap<String, String>>) -> MutexGuard<'_, HashMap<String, String>> {
+    names.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Normalize every name of `path` to NFC, the form OneDrive stores names in.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_string_lossy().nfc().collect::<String>().into(),
+            other => other.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+/// Percent-encode a single name for a Graph URL.
+pub fn encode_name(name: &str) -> String {
+    utf8_percent_encode(name, URL_SEGMENT).to_string()
+}
+
+/// Percent-encode every name of `path`, e.g. `/a b/c#d` to `/a%20b/c%23d`.
+/// The drive root encodes to an empty string.
+pub fn encode_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(encode_name(&name.to_string_lossy())),
+            _ => None,
+        })
+        .fold(String::new(), |mut encoded, name| {
+            encoded.push('/');
+            encoded.push_str(&name);
+            encoded
+        })
+}
+
+impl OnedriveInner {
+    /// Validate every name of `path` joined onto the folder of the backend,
+    /// without any network call.
+    pub fn validate_path(&self, path: &Path) -> Result<PathBuf, Error> {
+        let path = normalize_path(&RelativePath::new(path)?.to_path(&self.folder));
+
+        for component in path.components() {
+            match component {
+                Component::RootDir => {}
+                Component::Normal(name) => {
+                    validate_name(&name.to_string_lossy()).map_err(|reason| {
+                        Error::InvalidName {
+                            path: path.to_string_lossy().to_string(),
+                            name: name.to_string_lossy().to_string(),
+                            reason,
+                        }
+                    })?;
+                }
+                _ => {
+                    return Err(Error::InvalidPath {
+                        path: path.to_string_lossy().to_string(),
+                    })
+                }
+            }
+        }
+
+        let len = path.to_string_lossy().chars().count();
+        if len > MAX_PATH_LENGTH {
+            return Err(Error::PathTooLong {
+                path: path.to_string_lossy().to_string(),
+                len,
+            });
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ApiType;
+    use super::*;
+
+    #[test]
+    fn invalid_names() {
+        assert_eq!(
+            validate_name("a?.txt"),
+            Err(InvalidNameReason::InvalidChar('?'))
+        );
+        assert_eq!(validate_name("name."), Err(InvalidNameReason::TrailingDot));
+        assert_eq!(
+            validate_name("name "),
+            Err(InvalidNameReason::TrailingSpace)
+        );
+        assert_eq!(validate_name("con.txt"), Err(InvalidNameReason::Reserved));
+        assert_eq!(validate_name("LPT1"), Err(InvalidNameReason::Reserved));
+        assert_eq!(
+            validate_name("~$doc.docx"),
+            Err(InvalidNameReason::Reserved)
+        );
+        assert!(validate_name("console.txt").is_ok());
+        assert!(validate_name("备份 2024.tar").is_ok());
+    }
+
+    #[test]
+    fn encode_awkward_names() {
+        assert_eq!(encode_name("a#b%20c?.txt"), "a%23b%2520c%3F.txt");
+        assert_eq!(
+            encode_path(Path::new("/备份/2024 年/a+b&c=d.txt")),
+            "/%E5%A4%87%E4%BB%BD/2024%20%E5%B9%B4/a%2Bb%26c%3Dd.txt"
+        );
+        assert_eq!(encode_path(Path::new("/")), "");
+
+        // Decomposed `é` is stored composed
+        assert_eq!(
+            normalize_path(Path::new("/cafe\u{301}/x")),
+            Path::new("/caf\u{e9}/x")
+        );
+        assert_eq!(
+            ApiType::ChinaApi.item_url(Path::new("/备份/a#b%20c?.txt")),
+            "https://microsoftgraph.chinacloudapi.cn/v1.0/me/drive/root:/%E5%A4%87%E4%BB%BD/a%23b%2520c%3F.txt"
+        );
+        assert_eq!(
+            ApiType::Common.child_url("0123", "a#b.txt"),
+            "https://graph.microsoft.com/v1.0/me/drive/items/0123:/a%23b.txt:"
+        );
+    }
+
+    #[test]
+    fn sanitize_reversible() {
+        let sanitizer = Sanitizer::new();
+        for name in [
+            "a:b*c?.txt",
+            " name.",
+            "CON.txt",
+            "com1",
+            "plain.txt",
+            "x|y ",
+            "a＾b",
+            "会議？：メモ.txt",
+        ] {
+            let sanitized = sanitizer.sanitize(name);
+            assert!(validate_name(&sanitized).is_ok(), "{}", sanitized);
+            // Restoring doesn't depend on the names sanitized before
+            assert_eq!(Sanitizer::new().restore(&sanitized), name);
+        }
+        assert_eq!(sanitizer.sanitize("a?.txt"), "a＾？.txt");
+        assert_eq!(sanitizer.sanitize("plain.txt"), "plain.txt");
+
+        let sanitizer = Sanitizer::new().map(':', '_');
+        assert_eq!(sanitizer.sanitize("12:30.log"), "12＾_30.log");
+        assert_eq!(sanitizer.restore("12＾_30.log"), "12:30.log");
+        assert_eq!(sanitizer.restore("12_30.log"), "12_30.log");
+    }
+
+    #[test]
+    fn sanitize_reserved() {
+        for name in [
+            "~$doc.docx",
+            "desktop.ini",
+            "Desktop.INI",
+            ".lock",
+            "a_vti_b_VTI_c",
+            "LPT1.log",
+        ] {
+            assert!(validate_name(name).is_err(), "{}", name);
+            let sanitized = Sanitizer::new().sanitize(name);
+            assert!(validate_name(&sanitized).is_ok(), "{}", sanitized);
+            assert_eq!(Sanitizer::new().restore(&sanitized), name);
+        }
+
+        // Names created on OneDrive are returned as they are
+        let sanitizer = Sanitizer::new();
+        assert_eq!(sanitizer.restore("会議？：メモ.txt"), "会議？：メモ.txt");
+        assert_ne!(sanitizer.sanitize("a:b"), "a：b");
+        assert_eq!(sanitizer.restore("a：b"), "a：b");
+    }
+
+    #[test]
+    fn sanitize_long_names() {
+        let sanitizer = Sanitizer::new();
+        let names = ["1", "2"].map(|n| format!("{}{}.tar", "a".repeat(300), n));
+        let shortened = names.clone().map(|name| sanitizer.sanitize(&name));
+        assert_ne!(shortened[0], shortened[1]);
+        for (name, shortened) in names.iter().zip(&shortened) {
+            assert_eq!(shortened.chars().count(), MAX_NAME_LENGTH);
+            assert!(shortened.ends_with(".tar"));
+            assert!(validate_name(shortened).is_ok());
+            assert_eq!(sanitizer.restore(shortened), *name);
+        }
+
+        // Restored in a later run only with the persisted mapping
+        assert_eq!(Sanitizer::new().restore(&shortened[0]), shortened[0]);
+        let restarted = Sanitizer::new().with_shortened(sanitizer.shortened());
+        assert_eq!(restarted.restore(&shortened[1]), names[1]);
+
+        // A replacement isn't cut in half
+        let name = format!("{}{}.tar", "a".repeat(201), "?".repeat(30));
+        let shortened = sanitizer.sanitize(&name);
+        assert!(validate_name(&shortened).is_ok());
+        assert!(!shortened.contains("＾~"));
+    }
+}
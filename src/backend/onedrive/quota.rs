@@ -0,0 +1,46 @@
+use serde::Deserialize;
+use snafu::ResultExt;
+
+use crate::Quota;
+
+use super::{Error, OnedriveInner, QuotaSnafu};
+
+#[derive(Debug, Deserialize)]
+struct Drive {
+    quota: Option<DriveQuota>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DriveQuota {
+    total: u64,
+    used: u64,
+    remaining: u64,
+    deleted: Option<u64>,
+}
+
+impl OnedriveInner {
+    pub async fn quota(&self) -> Result<Option<Quota>, Error> {
+        let url = format!("{}/me/drive", self.api_type.get_graph_url());
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await
+            .context(QuotaSnafu)?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let drive = response.json::<Drive>().await.context(QuotaSnafu)?;
+                Ok(drive.quota.map(|quota| Quota {
+                    total: quota.total,
+                    used: quota.used,
+                    remaining: quota.remaining,
+                    deleted: quota.deleted,
+                }))
+            }
+            _ => Err(Error::Quota {
+                source: response.error_for_status().unwrap_err(),
+            }),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+
+use super::{CreateLinkSnafu, Error, GetItemSnafu, InviteSnafu, OnedriveInner};
+
+/// The kind of sharing link to create.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkType {
+    /// A read-only link.
+    #[default]
+    View,
+    /// A read-write link.
+    Edit,
+    /// A link that can be embedded into a web page.
+    /// Only available for OneDrive personal.
+    Embed,
+}
+
+/// Who is able to use a sharing link.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LinkScope {
+    /// Anyone with the link, no sign-in required.
+    #[default]
+    Anonymous,
+    /// Anyone signed in to the same tenant.
+    /// Not available for OneDrive personal.
+    Organization,
+    /// Only people who already have access to the item.
+    Users,
+}
+
+/// The role granted to invited users.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Role {
+    #[default]
+    Read,
+    Write,
+}
+
+/// Options for [`crate::backend::Onedrive::create_link`].
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkOptions {
+    #[serde(rename = "type")]
+    pub link_type: LinkType,
+    pub scope: LinkScope,
+    /// The link stops working after this time.
+    #[serde(rename = "expirationDateTime", skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<DateTime<Utc>>,
+    /// Password required to open the link. Only available for OneDrive personal.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Options for [`crate::backend::Onedrive::invite`].
+#[derive(Debug, Clone)]
+pub struct InviteOptions {
+    /// Email addresses of the people to invite.
+    pub recipients: Vec<String>,
+    pub role: Role,
+    /// Message included in the invitation mail.
+    pub message: Option<String>,
+    pub require_sign_in: bool,
+    /// Whether Microsoft should send an invitation mail to the recipients.
+    pub send_invitation: bool,
+    /// The permission stops working after this time.
+    pub expiration: Option<DateTime<Utc>>,
+    /// Password required to open the item. Only available for OneDrive personal.
+    pub password: Option<String>,
+}
+
+impl Default for InviteOptions {
+    fn default() -> Self {
+        Self {
+            recipients: Vec::new(),
+            role: Role::Read,
+            message: None,
+            require_sign_in: true,
+            send_invitation: true,
+            expiration: None,
+            password: None,
+        }
+    }
+}
+
+/// A sharing link of an item.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharingLink {
+    #[serde(rename = "type")]
+    pub link_type: Option<LinkType>,
+    pub scope: Option<LinkScope>,
+    pub web_url: String,
+    pub web_html: Option<String>,
+}
+
+/// An identity a permission was granted to.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Identity {
+    pub id: Option<String>,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub email: Option<String>,
+    pub sign_in_required: Option<bool>,
+}
+
+/// A permission on an item, as returned by `createLink` and `invite`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Permission {
+    pub id: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub link: Option<SharingLink>,
+    pub invitation: Option<Invitation>,
+    #[serde(
+        rename = "grantedToV2",
+        default,
+        deserialize_with = "deserialize_granted_to"
+    )]
+    pub granted_to: Option<Identity>,
+    #[serde(rename = "expirationDateTime")]
+    pub expiration: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub has_password: bool,
+}
+
+/// `grantedToV2` wraps the identity into `user`, `group` or `siteUser`.
+fn deserialize_granted_to<'de, D>(deserializer: D) -> Result<Option<Identity>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Option::<serde_json::Value>::deserialize(deserializer)?;
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    ["user", "group", "siteUser"]
+        .iter()
+        .find_map(|key| value.get(key))
+        .map(|identity| Identity::deserialize(identity.clone()))
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize)]
+struct InviteResponse {
+    value: Vec<Permission>,
+}
+
+impl OnedriveInner {
+    pub async fn create_link(
+        &self,
+        path: &Path,
+        options: &LinkOptions,
+    ) -> Result<Permission, Error> {
+        let item_id = self.get_item_id(path).await?;
+
+        let url = format!(
+            "{}/me/drive/items/{}/createLink",
+            self.api_type.get_graph_url(),
+            item_id
+        );
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .json(options)
+            .send()
+            .await
+            .context(CreateLinkSnafu)?;
+
+        match response.status() {
+            reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => {
+                response.json::<Permission>().await.context(CreateLinkSnafu)
+            }
+            _ => Err(Error::CreateLink {
+                source: response.error_for_status().unwrap_err(),
+            }),
+        }
+    }
+
+    pub async fn invite(
+        &self,
+        path: &Path,
+        options: &InviteOptions,
+    ) -> Result<Vec<Permission>, Error> {
+        let item_id = self.get_item_id(path).await?;
+
+        let url = format!(
+            "{}/me/drive/items/{}/invite",
+            self.api_type.get_graph_url(),
+            item_id
+        );
+
+        let recipients = options
+            .recipients
+            .iter()
+            .map(|email| serde_json::json!({ "email": email }))
+            .collect::<Vec<_>>();
+        let mut body = serde_json::json!({
+            "recipients": recipients,
+            "roles": [options.role],
+            "requireSignIn": options.require_sign_in,
+            "sendInvitation": options.send_invitation,
+        });
+        if let Some(message) = &options.message {
+            body["message"] = serde_json::json!(message);
+        }
+        if let Some(expiration) = &options.expiration {
+            body["expirationDateTime"] = serde_json::json!(expiration);
+        }
+        if let Some(password) = &options.password {
+            body["password"] = serde_json::json!(password);
+        }
+
+        let response = reqwest::Client::new()
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .json(&body)
+            .send()
+            .await
+            .context(InviteSnafu)?;
+
+        match response.status() {
+            reqwest::StatusCode::OK | reqwest::StatusCode::CREATED => Ok(response
+                .json::<InviteResponse>()
+                .await
+                .context(InviteSnafu)?
+                .value),
+            _ => Err(Error::Invite {
+                source: response.error_for_status().unwrap_err(),
+            }),
+        }
+    }
+
+    /// Get the id of an existing item, `path` is relative to `folder`.
+    async fn get_item_id(&self, path: &Path) -> Result<String, Error> {
+        let path = self.validate_path(path)?;
+
+        let url = self.api_type.item_url(&path);
+        let response = reqwest::Client::new()
+            .get(&url)
+            .header("Authorization", format!("Bearer {}", self.access_token))
+            .send()
+            .await
+            .with_context(|_| GetItemSnafu {
+                path: path.to_string_lossy().to_string(),
+            })?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => {
+                let json = response
+                    .json::<serde_json::Value>()
+                    .await
+                    .with_context(|_| GetItemSnafu {
+                        path: path.to_string_lossy().to_string(),
+                    })?;
+
+                let item_id =
+                    json.get("id")
+                        .and_then(|id| id.as_str())
+                        .ok_or_else(|| Error::Parsing {
+                            context: json.to_string(),
+                        })?;
+
+                Ok(item_id.to_string())
+            }
+            reqwest::StatusCode::NOT_FOUND => Err(Error::ItemNotFound {
+                path: path.to_string_lossy().to_string(),
+            }),
+            _ => Err(Error::GetItem {
+                path: path.to_string_lossy().to_string(),
+                source: response.error_for_status().unwrap_err(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_permission() {
+        let json = serde_json::json!({
+            "id": "123ABC",
+            "roles": ["write"],
+            "grantedToV2": {
+                "user": { "id": "42", "displayName": "Robin", "email": "robin@contoso.com" }
+            },
+            "invitation": { "email": "robin@contoso.com", "signInRequired": true },
+            "link": { "type": "edit", "scope": "organization", "webUrl": "https://1drv.ms/A6913278E564460AA616C71B28AD6EB6" }
+        });
+        let permission = Permission::deserialize(json).unwrap();
+
+        assert_eq!(permission.roles, vec!["write"]);
+        assert_eq!(
+            permission.granted_to.unwrap().email.unwrap(),
+            "robin@contoso.com"
+        );
+        let link = permission.link.unwrap();
+        assert_eq!(link.link_type, Some(LinkType::Edit));
+        assert_eq!(link.scope, Some(LinkScope::Organization));
+        assert!(!permission.has_password);
+    }
+}
@@ -76,10 +76,8 @@ impl OnedriveInner {
         reader.read_to_end(&mut buf).await.context(ReadFileSnafu)?;
 
         let url = format!(
-            "{}/me/drive/items/{}:/{}:/content",
-            self.api_type.get_graph_url(),
-            parent_id,
-            file_name
+            "{}/content",
+            self.api_type.child_url(&parent_id, &file_name)
         );
         let response = reqwest::Client::new()
             .put(&url)
@@ -201,10 +199,8 @@ impl OnedriveInner {
         let (parent_id, file_name) = self.calu_path(path).await?;
 
         let url = format!(
-            "{}/me/drive/items/{}:/{}:/createUploadSession",
-            self.api_type.get_graph_url(),
-            parent_id,
-            file_name
+            "{}/createUploadSession",
+            self.api_type.child_url(&parent_id, &file_name)
         );
         let response = reqwest::Client::new()
             .post(&url)
@@ -235,16 +231,7 @@ impl OnedriveInner {
     }
 
     async fn get_parent_id(&self, folder: &Path) -> Result<String, Error> {
-        let forder_str = folder.to_string_lossy();
-        let url = if forder_str == "/" {
-            format!("{}/me/drive/root", self.api_type.get_graph_url())
-        } else {
-            format!(
-                "{}/me/drive/root:/{}/",
-                self.api_type.get_graph_url(),
-                forder_str
-            )
-        };
+        let url = self.api_type.item_url(folder);
 
         let response = reqwest::Client::new()
             .get(&url)
@@ -334,12 +321,7 @@ impl OnedriveInner {
     }
 
     async fn calu_path(&self, path: &Path) -> Result<(String, String), Error> {
-        if path.has_root() {
-            return Err(Error::InvalidPath {
-                path: path.to_string_lossy().to_string(),
-            });
-        }
-        let path = self.folder.join(path);
+        let path = self.validate_path(path)?;
         let parent = path.parent().ok_or(Error::InvalidPath {
             path: path.to_string_lossy().to_string(),
         })?;
@@ -0,0 +1,206 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest_dav::{Auth, ClientBuilder};
+use snafu::ResultExt;
+
+use super::{
+    capabilities::nextcloud_uploads_url, stream, BuildClientSnafu, BuildHttpClientSnafu, Error,
+    InvalidCertificateSnafu, InvalidProxySnafu, NextcloudChunking, Probe, Webdav,
+};
+
+/// Transport settings of a [`Webdav`] backend.
+#[derive(Debug)]
+pub struct WebdavBuilder {
+    url: String,
+    folder: PathBuf,
+    auth: Auth,
+    headers: Vec<(String, String)>,
+    identity: Option<Vec<u8>>,
+    ca_certificates: Vec<Vec<u8>>,
+    accept_invalid_certs: bool,
+    proxy: Option<String>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    http1_only: bool,
+    probe: Probe,
+}
+
+impl WebdavBuilder {
+    pub(super) fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            folder: PathBuf::from("/"),
+            auth: Auth::Anonymous,
+            headers: Vec::new(),
+            identity: None,
+            ca_certificates: Vec::new(),
+            accept_invalid_certs: false,
+            proxy: None,
+            timeout: None,
+            connect_timeout: None,
+            http1_only: false,
+            probe: Probe::default(),
+        }
+    }
+
+    /// The folder on the server all upload paths are joined onto, `/` by default.
+    pub fn folder(mut self, folder: impl AsRef<Path>) -> Self {
+        self.folder = Path::new("/").join(folder);
+        self
+    }
+
+    /// Basic or digest authentication.
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Send `Authorization: Bearer <token>` with every request.
+    pub fn bearer_token(self, token: impl AsRef<str>) -> Self {
+        self.header(AUTHORIZATION.as_str(), format!("Bearer {}", token.as_ref()))
+    }
+
+    /// Send an extra header with every request.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Authenticate with a client certificate.
+    /// pem: The private key and certificate chain, PEM encoded.
+    pub fn identity_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.identity = Some(pem.into());
+        self
+    }
+
+    /// Trust an additional, PEM encoded root certificate, e.g. a private CA.
+    pub fn ca_certificate_pem(mut self, pem: impl Into<Vec<u8>>) -> Self {
+        self.ca_certificates.push(pem.into());
+        self
+    }
+
+    /// Accept any server certificate, e.g. a self-signed one of a NAS.
+    /// This makes the connection vulnerable to man-in-the-middle attacks.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Send every request through a proxy, e.g. `http://proxy:3128`.
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// The timeout of a whole request, including the body of an upload.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The timeout of establishing a connection.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Only use HTTP/1.1, for servers with broken HTTP/2 support.
+    pub fn http1_only(mut self) -> Self {
+        self.http1_only = true;
+        self
+    }
+
+    /// How the server is checked when building the backend, `OPTIONS` on the folder by default.
+    pub fn probe(mut self, probe: Probe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Build the backend and detect the capabilities of the server,
+    /// unless the probe is disabled.
+    pub async fn build(self) -> Result<Webdav, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let header = HeaderName::from_bytes(name.as_bytes())
+                .ok()
+                .zip(HeaderValue::from_str(value).ok());
+            let Some((name, mut value)) = header else {
+                return Err(Error::InvalidHeader { name: name.clone() });
+            };
+            if name == AUTHORIZATION {
+                value.set_sensitive(true);
+            }
+            headers.append(name, value);
+        }
+
+        let mut agent = reqwest::Client::builder()
+            .default_headers(headers)
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(identity) = &self.identity {
+            agent = agent
+                .identity(reqwest::Identity::from_pem(identity).context(InvalidCertificateSnafu)?);
+        }
+        for certificate in &self.ca_certificates {
+            agent = agent.add_root_certificate(
+                reqwest::Certificate::from_pem(certificate).context(InvalidCertificateSnafu)?,
+            );
+        }
+        if let Some(proxy) = &self.proxy {
+            agent = agent.proxy(reqwest::Proxy::all(proxy).context(InvalidProxySnafu)?);
+        }
+        if let Some(timeout) = self.timeout {
+            agent = agent.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            agent = agent.connect_timeout(timeout);
+        }
+        if self.http1_only {
+            agent = agent.http1_only();
+        }
+        let agent = agent.build().context(BuildHttpClientSnafu)?;
+
+        let client = ClientBuilder::new()
+            .set_agent(agent)
+            .set_host(self.url)
+            .set_auth(self.auth)
+            .build()
+            .context(BuildClientSnafu)?;
+        let mut webdav = Webdav {
+            client,
+            folder: self.folder,
+            check_quota: false,
+            check_conflict: false,
+            checksum: None,
+            verify_etag: false,
+            chunking: None,
+            partial_update: None,
+            lock_timeout: None,
+            read_buffer_size: stream::DEFAULT_READ_BUFFER_SIZE,
+            probe: self.probe,
+            capabilities: OnceLock::new(),
+            collections: Mutex::new(HashSet::new()),
+        };
+
+        let probe = match &webdav.probe {
+            Probe::None => None,
+            Probe::Folder => Some(webdav.folder.clone()),
+            Probe::Path(path) => Some(path.clone()),
+        };
+        if let Some(path) = probe {
+            let capabilities = webdav.detect_capabilities(&path).await?;
+            // Nextcloud supports chunking, its upload folder follows from the files URL
+            if capabilities.nextcloud_chunking {
+                webdav.chunking =
+                    nextcloud_uploads_url(&webdav.client.host).map(NextcloudChunking::new);
+            }
+            let _ = webdav.capabilities.set(capabilities);
+        }
+        Ok(webdav)
+    }
+}
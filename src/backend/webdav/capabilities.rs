@@ -0,0 +1,138 @@
+use std::path::{Path, PathBuf};
+
+use reqwest::header::HeaderMap;
+use reqwest_dav::Dav2xx as _;
+use snafu::ResultExt;
+use tracing::debug;
+
+use super::{Error, ProbeSnafu, Webdav};
+
+/// How the server is checked when building the backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Probe {
+    /// Don't contact the server until the first upload.
+    None,
+    /// Send `OPTIONS` to the folder of the backend.
+    #[default]
+    Folder,
+    /// Send `OPTIONS` to this absolute path on the server.
+    Path(PathBuf),
+}
+
+/// What the server announced it supports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The compliance classes and extensions of the `DAV` header, e.g. `1`, `2`, `3`.
+    pub dav: Vec<String>,
+    /// `LOCK` and `UNLOCK`, DAV class 2.
+    pub locking: bool,
+    /// sabre/dav partial updates with `PATCH`.
+    pub partial_update: bool,
+    /// The server is a Nextcloud, which supports the chunking v2 protocol.
+    pub nextcloud_chunking: bool,
+    /// The RFC 4331 quota properties are reported.
+    pub quota: bool,
+}
+
+impl Capabilities {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let dav = headers
+            .get_all("dav")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|class| class.trim().to_string())
+            .filter(|class| !class.is_empty())
+            .collect::<Vec<_>>();
+        let accept_patch = headers
+            .get_all("accept-patch")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("sabredav-partialupdate"));
+
+        Self {
+            locking: dav.iter().any(|class| class == "2"),
+            partial_update: accept_patch
+                || dav.iter().any(|class| class == "sabredav-partialupdate"),
+            nextcloud_chunking: dav
+                .iter()
+                .any(|class| class.starts_with("nextcloud-") || class.starts_with("nc-")),
+            dav,
+            quota: false,
+        }
+    }
+}
+
+impl Webdav {
+    /// The capabilities of the server, detected once.
+    pub async fn capabilities(&self) -> Result<Capabilities, Error> {
+        if let Some(capabilities) = self.capabilities.get() {
+            return Ok(capabilities.clone());
+        }
+        let path = match &self.probe {
+            Probe::Path(path) => path.clone(),
+            Probe::None | Probe::Folder => self.folder.clone(),
+        };
+        let capabilities = self.detect_capabilities(&path).await?;
+        Ok(self.capabilities.get_or_init(|| capabilities).clone())
+    }
+
+    pub(super) async fn detect_capabilities(&self, path: &Path) -> Result<Capabilities, Error> {
+        let href = Self::collection_href(path);
+        let response = self
+            .client
+            .start_request(reqwest::Method::OPTIONS, &href)
+            .await
+            .with_context(|_| ProbeSnafu { path: &href })?
+            .send()
+            .await
+            .map_err(reqwest_dav::Error::from)
+            .with_context(|_| ProbeSnafu { path: &href })?
+            .dav2xx()
+            .await
+            .with_context(|_| ProbeSnafu { path: &href })?;
+
+        let mut capabilities = Capabilities::from_headers(response.headers());
+        // A failed PROPFIND only means the quota is unknown
+        capabilities.quota = matches!(self.quota().await, Ok(Some(_)));
+        debug!("WebDAV capabilities of {}: {:?}", href, capabilities);
+        Ok(capabilities)
+    }
+}
+
+/// The chunking v2 upload folder of a Nextcloud files URL,
+/// e.g. `https://cloud/remote.php/dav/files/alice` to `https://cloud/remote.php/dav/uploads/alice`.
+pub(super) fn nextcloud_uploads_url(url: &str) -> Option<String> {
+    let (base, rest) = url.split_once("/remote.php/dav/files/")?;
+    let user = rest.split('/').next().filter(|user| !user.is_empty())?;
+    Some(format!("{}/remote.php/dav/uploads/{}", base, user))
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn parse_capabilities() {
+        let mut headers = HeaderMap::new();
+        headers.append("dav", HeaderValue::from_static("1, 3, extended-mkcol"));
+        headers.append(
+            "dav",
+            HeaderValue::from_static("nextcloud-checksum-update, nc-calendar-search"),
+        );
+        let capabilities = Capabilities::from_headers(&headers);
+        assert!(!capabilities.locking);
+        assert!(!capabilities.partial_update);
+        assert!(capabilities.nextcloud_chunking);
+        assert_eq!(capabilities.dav.len(), 5);
+
+        assert_eq!(
+            nextcloud_uploads_url("https://cloud.example.com/remote.php/dav/files/alice/backup")
+                .unwrap(),
+            "https://cloud.example.com/remote.php/dav/uploads/alice"
+        );
+        assert!(nextcloud_uploads_url("https://cloud.example.com/remote.php/webdav").is_none());
+    }
+}
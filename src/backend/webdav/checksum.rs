@@ -0,0 +1,197 @@
+use std::time::UNIX_EPOCH;
+
+use base64::Engine as _;
+use md5::{Digest as _, Md5};
+use sha1::Sha1;
+use snafu::ResultExt;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+
+use crate::{hex, AsyncBufReadSeek, Metadata};
+
+use super::{Error, ReadFileSnafu};
+
+/// The checksum sent with an upload in the `OC-Checksum` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Checksum {
+    /// Also sent as `Content-MD5`.
+    Md5,
+    Sha1,
+}
+
+/// The digests of a file, computed before uploading it.
+#[derive(Debug, Default)]
+pub(super) struct Digests {
+    md5: Option<Vec<u8>>,
+    sha1: Option<Vec<u8>>,
+}
+
+impl Digests {
+    /// Hash the whole reader and rewind it.
+    pub(super) async fn compute(
+        reader: &mut Box<dyn AsyncBufReadSeek>,
+        md5: bool,
+        sha1: bool,
+    ) -> Result<Self, Error> {
+        if !md5 && !sha1 {
+            return Ok(Self::default());
+        }
+
+        let mut md5 = md5.then(Md5::new);
+        let mut sha1 = sha1.then(Sha1::new);
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buffer).await.context(ReadFileSnafu)?;
+            if n == 0 {
+                break;
+            }
+            if let Some(md5) = &mut md5 {
+                md5.update(&buffer[..n]);
+            }
+            if let Some(sha1) = &mut sha1 {
+                sha1.update(&buffer[..n]);
+            }
+        }
+        reader
+            .seek(tokio::io::SeekFrom::Start(0))
+            .await
+            .context(ReadFileSnafu)?;
+
+        Ok(Self {
+            md5: md5.map(|md5| md5.finalize().to_vec()),
+            sha1: sha1.map(|sha1| sha1.finalize().to_vec()),
+        })
+    }
+
+    /// The value of the `OC-Checksum` header, e.g. `SHA1:2fd4e1c6...`.
+    pub(super) fn oc_checksum(&self, checksum: Checksum) -> Option<String> {
+        match checksum {
+            Checksum::Md5 => self.md5.as_ref().map(|d| format!("MD5:{}", hex(d))),
+            Checksum::Sha1 => self.sha1.as_ref().map(|d| format!("SHA1:{}", hex(d))),
+        }
+    }
+
+    /// Add the checksum and metadata headers to a request creating the file.
+    pub(super) fn headers(
+        &self,
+        request: reqwest::RequestBuilder,
+        checksum: Option<Checksum>,
+        metadata: &Metadata,
+    ) -> reqwest::RequestBuilder {
+        let request = self.move_headers(request, checksum, metadata);
+        match (checksum, &self.md5) {
+            (Some(Checksum::Md5), Some(md5)) => request.header(
+                "content-md5",
+                base64::engine::general_purpose::STANDARD.encode(md5),
+            ),
+            _ => request,
+        }
+    }
+
+    /// Add the checksum and metadata headers to a MOVE assembling the file,
+    /// without `Content-MD5` as the request has no body.
+    pub(super) fn move_headers(
+        &self,
+        request: reqwest::RequestBuilder,
+        checksum: Option<Checksum>,
+        metadata: &Metadata,
+    ) -> reqwest::RequestBuilder {
+        let mut request = request;
+        if let Some(checksum) = checksum.and_then(|checksum| self.oc_checksum(checksum)) {
+            request = request.header("oc-checksum", checksum);
+        }
+        let mtime = metadata
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+        if let Some(mtime) = mtime.map(|mtime| mtime.as_secs()) {
+            request = request.header("x-oc-mtime", mtime);
+        }
+        request
+    }
+
+    /// Compare the checksum or ETag the server returned with the local digests.
+    /// Servers echoing neither are trusted.
+    pub(super) fn verify(
+        &self,
+        headers: &reqwest::header::HeaderMap,
+        verify_etag: bool,
+        path: &str,
+    ) -> Result<(), Error> {
+        // e.g. `SHA1:2fd4e1c6... MD5:9e107d9d...`
+        let echoed = headers
+            .get_all("oc-checksum")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split_whitespace())
+            .filter_map(|value| value.split_once(':'));
+        for (kind, actual) in echoed {
+            let expected = match kind.to_ascii_uppercase().as_str() {
+                "MD5" => self.md5.as_deref(),
+                "SHA1" => self.sha1.as_deref(),
+                _ => None,
+            };
+            if let Some(expected) = expected.map(hex) {
+                if !expected.eq_ignore_ascii_case(actual) {
+                    return Err(Error::ChecksumMismatch {
+                        path: path.to_string(),
+                        expected,
+                        actual: actual.to_string(),
+                    });
+                }
+            }
+        }
+
+        if verify_etag {
+            let etag = headers
+                .get(reqwest::header::ETAG)
+                .and_then(|value| value.to_str().ok());
+            if let (Some(etag), Some(md5)) = (etag, &self.md5) {
+                let actual = etag.trim_start_matches("W/").trim_matches('"');
+                let expected = hex(md5);
+                if !expected.eq_ignore_ascii_case(actual) {
+                    return Err(Error::ChecksumMismatch {
+                        path: path.to_string(),
+                        expected,
+                        actual: actual.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn verify_echoed_checksum() {
+        let mut reader: Box<dyn AsyncBufReadSeek> =
+            Box::new(std::io::Cursor::new(b"hello".to_vec()));
+        let digests = Digests::compute(&mut reader, true, true).await.unwrap();
+        assert_eq!(
+            digests.oc_checksum(Checksum::Sha1).unwrap(),
+            "SHA1:aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "oc-checksum",
+            HeaderValue::from_static("MD5:5D41402ABC4B2A76B9719D911017C592"),
+        );
+        headers.insert(
+            "etag",
+            HeaderValue::from_static("\"5d41402abc4b2a76b9719d911017c592\""),
+        );
+        digests.verify(&headers, true, "/a").unwrap();
+
+        headers.insert("etag", HeaderValue::from_static("\"0-1\""));
+        assert!(digests.verify(&headers, false, "/a").is_ok());
+        assert!(matches!(
+            digests.verify(&headers, true, "/a"),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+}
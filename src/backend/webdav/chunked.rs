@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use reqwest_dav::{list_cmd::ListEntity, ClientBuilder, Dav2xx as _};
+use sha2::{Digest as _, Sha256};
+use snafu::ResultExt;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+use tracing::{debug, warn};
+
+use crate::{hex, AsyncBufReadSeek, Metadata};
+
+use super::{
+    checksum::Digests,
+    stream::{self, SharedReader},
+    BuildClientSnafu, CreateCollectionSnafu, Error, ListFilesSnafu, Precondition, ReadFileSnafu,
+    UploadChunkSnafu, Webdav,
+};
+
+/// The default size of each chunk.
+/// 10 MB
+const DEFAULT_CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+/// Nextcloud rejects chunks smaller than 5 MB, except for the last one.
+const MIN_CHUNK_SIZE: u64 = 5 * 1024 * 1024;
+/// Nextcloud rejects chunks larger than 5 GB.
+const MAX_CHUNK_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+/// Nextcloud rejects uploads of more chunks.
+const MAX_CHUNKS: u64 = 10_000;
+/// How many times missing chunks are uploaded before giving up.
+const MAX_ATTEMPTS: usize = 3;
+
+/// Settings of the Nextcloud/ownCloud chunking v2 protocol.
+#[derive(Debug, Clone)]
+pub struct NextcloudChunking {
+    uploads_url: String,
+    pub(super) chunk_size: u64,
+}
+
+impl NextcloudChunking {
+    /// uploads_url: The upload folder of the user,
+    /// e.g. `https://cloud.example.com/remote.php/dav/uploads/alice`.
+    pub fn new(uploads_url: impl Into<String>) -> Self {
+        Self {
+            uploads_url: uploads_url.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// The size of each chunk, clamped between 5 MB and 5 GB.
+    /// Files not larger than one chunk are uploaded with a single PUT, files of
+    /// more than 10 000 chunks with larger chunks.
+    pub fn chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// The size of the chunks of a file of `size` bytes.
+    fn chunk_size_for(&self, size: u64) -> u64 {
+        self.chunk_size
+            .max(size.div_ceil(MAX_CHUNKS))
+            .min(MAX_CHUNK_SIZE)
+    }
+}
+
+impl Webdav {
+    /// Upload numbered chunks into a transfer collection and MOVE the assembled
+    /// `.file` onto `href`. Chunks missing after a failure are uploaded again.
+    /// The transfer is kept when the upload fails, so uploading the same file
+    /// again only sends the chunks the server doesn't have yet.
+    #[allow(clippy::too_many_arguments)]
+    pub(super) async fn upload_chunked(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        href: &str,
+        precondition: &Precondition,
+        chunking: &NextcloudChunking,
+        digests: &Digests,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        let client = ClientBuilder::new()
+            .set_agent(self.client.agent.clone())
+            .set_host(chunking.uploads_url.clone())
+            .set_auth(self.client.auth.clone())
+            .build()
+            .context(BuildClientSnafu)?;
+        let destination = self.url(href);
+        let chunk_size = chunking.chunk_size_for(size);
+        let transfer = transfer_href(&destination, size, chunk_size);
+
+        let response = client
+            .start_request(reqwest::Method::from_bytes(b"MKCOL").unwrap(), &transfer)
+            .await
+            .with_context(|_| CreateCollectionSnafu { path: &transfer })?
+            .header("destination", &destination)
+            .send()
+            .await
+            .map_err(reqwest_dav::Error::from)
+            .with_context(|_| CreateCollectionSnafu { path: &transfer })?;
+        // The transfer of an earlier attempt already exists
+        let resumed = response.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED;
+        if !resumed {
+            response
+                .dav2xx()
+                .await
+                .with_context(|_| CreateCollectionSnafu { path: &transfer })?;
+        }
+
+        let result = async {
+            let reader = SharedReader::new(reader);
+            self.upload_chunks(&client, &transfer, &reader, size, href, chunk_size, resumed)
+                .await?;
+
+            let request = self
+                .move_request(&client, &format!("{}/.file", transfer), href, precondition)
+                .await?
+                .header("oc-total-length", size);
+            // The checksum and mtime apply to the assembled file
+            let request = digests.move_headers(request, self.checksum, metadata);
+            Self::send_move(request, href).await
+        }
+        .await;
+
+        // Nextcloud removes transfers left behind after a while,
+        // only those which can't be completed anymore are removed now
+        if result.as_ref().is_err_and(Error::abandons_upload) {
+            debug!("Removing transfer {} after failed upload", transfer);
+            let _ = client.delete(&transfer).await;
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_chunks(
+        &self,
+        client: &reqwest_dav::Client,
+        transfer: &str,
+        reader: &SharedReader,
+        size: u64,
+        href: &str,
+        chunk_size: u64,
+        resumed: bool,
+    ) -> Result<(), Error> {
+        let mut missing = if resumed {
+            let uploaded = Self::uploaded_chunks(client, transfer).await?;
+            let missing = missing_chunks(&uploaded, size, chunk_size);
+            debug!("Continuing upload of {} with chunks {:?}", href, missing);
+            missing
+        } else {
+            (1..=size.div_ceil(chunk_size)).collect()
+        };
+
+        for _ in 0..MAX_ATTEMPTS {
+            for &chunk in &missing {
+                match self
+                    .upload_chunk(client, transfer, reader, size, href, chunk, chunk_size)
+                    .await
+                {
+                    Ok(()) => {}
+                    // The source can't be read, retrying won't help
+                    Err(e @ Error::ReadFile { .. }) => return Err(e),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+
+            let uploaded = Self::uploaded_chunks(client, transfer).await?;
+            missing = missing_chunks(&uploaded, size, chunk_size);
+            if missing.is_empty() {
+                return Ok(());
+            }
+            debug!("Uploading missing chunks {:?} of {}", missing, href);
+        }
+
+        Err(Error::MissingChunks {
+            path: href.to_string(),
+            chunks: missing,
+        })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_chunk(
+        &self,
+        client: &reqwest_dav::Client,
+        transfer: &str,
+        reader: &SharedReader,
+        size: u64,
+        href: &str,
+        chunk: u64,
+        chunk_size: u64,
+    ) -> Result<(), Error> {
+        let start_pos = (chunk - 1) * chunk_size;
+        let len = chunk_size.min(size - start_pos);
+
+        // The chunk is streamed like a single PUT, chunks may be up to 5 GB
+        reader
+            .clone()
+            .seek(tokio::io::SeekFrom::Start(start_pos))
+            .await
+            .context(ReadFileSnafu)?;
+        let read_error = stream::ReadError::default();
+        let response = match client
+            .start_request(reqwest::Method::PUT, &chunk_href(transfer, chunk))
+            .await
+            .with_context(|_| UploadChunkSnafu { path: href, chunk })?
+            .header(reqwest::header::CONTENT_LENGTH, len)
+            .header("destination", self.url(href))
+            .header("oc-total-length", size)
+            .body(stream::body(
+                reader.clone().take(len),
+                self.read_buffer_size,
+                read_error.clone(),
+            ))
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => match read_error.take() {
+                Some(source) => return Err(Error::ReadFile { source }),
+                None => {
+                    return Err(reqwest_dav::Error::from(e))
+                        .with_context(|_| UploadChunkSnafu { path: href, chunk })
+                }
+            },
+        };
+        response
+            .dav2xx()
+            .await
+            .with_context(|_| UploadChunkSnafu { path: href, chunk })?;
+        Ok(())
+    }
+
+    /// The sizes of the chunks on the server by number.
+    async fn uploaded_chunks(
+        client: &reqwest_dav::Client,
+        transfer: &str,
+    ) -> Result<HashMap<u64, u64>, Error> {
+        let entities = client
+            .list(transfer, reqwest_dav::Depth::Number(1))
+            .await
+            .context(ListFilesSnafu)?;
+
+        Ok(entities
+            .into_iter()
+            .filter_map(|entity| match entity {
+                ListEntity::File(file) => {
+                    let name = file.href.trim_end_matches('/').rsplit('/').next()?;
+                    Some((name.parse::<u64>().ok()?, file.content_length as u64))
+                }
+                ListEntity::Folder(_) => None,
+            })
+            .collect())
+    }
+}
+
+/// The chunks of a file of `size` bytes which are not `uploaded` with the expected size.
+fn missing_chunks(uploaded: &HashMap<u64, u64>, size: u64, chunk_size: u64) -> Vec<u64> {
+    let count = size.div_ceil(chunk_size);
+    (1..=count)
+        .filter(|chunk| {
+            let start_pos = (chunk - 1) * chunk_size;
+            let len = chunk_size.min(size - start_pos);
+            uploaded.get(chunk) != Some(&len)
+        })
+        .collect()
+}
+
+/// The transfer collection of an upload of `size` bytes to `destination`.
+/// The name only depends on the upload, so a later attempt finds the chunks
+/// of a failed one.
+fn transfer_href(destination: &str, size: u64, chunk_size: u64) -> String {
+    let hash = Sha256::new()
+        .chain_update(destination)
+        .chain_update(size.to_be_bytes())
+        .chain_update(chunk_size.to_be_bytes())
+        .finalize();
+    format!("/upload-{}", &hex(&hash)[..32])
+}
+
+/// Chunks are named by their number, zero padded to keep them sorted.
+fn chunk_href(transfer: &str, chunk: u64) -> String {
+    format!("{}/{:05}", transfer, chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_chunks() {
+        let uploaded = HashMap::from([(1, 10), (2, 7), (4, 5), (5, 10)]);
+        // Chunk 2 is incomplete and 3 wasn't uploaded, 5 doesn't belong to the file
+        assert_eq!(missing_chunks(&uploaded, 35, 10), vec![2, 3]);
+        // The last chunk is shorter
+        assert_eq!(missing_chunks(&uploaded, 40, 10), vec![2, 3, 4]);
+        assert!(missing_chunks(&HashMap::from([(1, 10), (2, 10)]), 20, 10).is_empty());
+        assert!(missing_chunks(&HashMap::new(), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_size_for() {
+        let chunking = NextcloudChunking::new("https://cloud.example.com");
+        assert_eq!(chunking.chunk_size_for(1024), DEFAULT_CHUNK_SIZE);
+        let size = 200 * 1024 * 1024 * 1024;
+        let chunk_size = chunking.chunk_size_for(size);
+        assert_eq!(size.div_ceil(chunk_size), MAX_CHUNKS);
+        assert_eq!(chunking.chunk_size_for(u64::MAX), MAX_CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use reqwest_dav::Dav2xx as _;
+use snafu::ResultExt;
+use tracing::{debug, warn};
+
+use super::{Error, LockSnafu, Webdav};
+
+const LOCK_BODY: &str = r#"<?xml version="1.0" encoding="utf-8" ?>
+    <D:lockinfo xmlns:D="DAV:">
+        <D:lockscope><D:exclusive/></D:lockscope>
+        <D:locktype><D:write/></D:locktype>
+    </D:lockinfo>
+"#;
+
+/// The shortest lock timeout, shorter ones would expire before they are refreshed.
+pub(super) const MIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An exclusive write lock, refreshed in the background until it is dropped.
+#[derive(Debug)]
+pub(super) struct Lock {
+    href: String,
+    /// e.g. `opaquelocktoken:e71d4fae-5dec-22d6-fea5-00a0c91e6be4`
+    pub(super) token: String,
+    /// Whether the LOCK created the file, which is left empty if the upload fails.
+    created: bool,
+    refresh_handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.refresh_handle.abort();
+    }
+}
+
+impl Webdav {
+    /// Lock `href`, creating an empty file if it doesn't exist.
+    pub(super) async fn lock(&self, href: &str, timeout: Duration) -> Result<Lock, Error> {
+        let response = self
+            .client
+            .start_request(reqwest::Method::from_bytes(b"LOCK").unwrap(), href)
+            .await
+            .with_context(|_| LockSnafu { path: href })?
+            .header("depth", "0")
+            .header("timeout", format!("Second-{}", timeout.as_secs()))
+            .header("content-type", "application/xml; charset=utf-8")
+            .body(LOCK_BODY)
+            .send()
+            .await
+            .map_err(reqwest_dav::Error::from)
+            .with_context(|_| LockSnafu { path: href })?;
+        if response.status() == reqwest::StatusCode::LOCKED {
+            return Err(Error::Locked {
+                path: href.to_string(),
+            });
+        }
+        let created = response.status() == reqwest::StatusCode::CREATED;
+        let response = response
+            .dav2xx()
+            .await
+            .with_context(|_| LockSnafu { path: href })?;
+
+        // The header value is a Coded-URL, e.g. `<opaquelocktoken:...>`
+        let token = response
+            .headers()
+            .get("lock-token")
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().trim_start_matches('<').trim_end_matches('>'))
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| Error::MissingLockToken {
+                path: href.to_string(),
+            })?
+            .to_string();
+        debug!("Locked {} with {}", href, token);
+
+        let refresh_handle = refresh_handle(
+            self.client.clone(),
+            href.to_string(),
+            token.clone(),
+            timeout,
+        );
+        Ok(Lock {
+            href: href.to_string(),
+            token,
+            created,
+            refresh_handle,
+        })
+    }
+
+    /// Delete the empty file the LOCK created after a failed upload.
+    /// Failures are only logged.
+    pub(super) async fn remove_created(&self, lock: &Lock) {
+        if !lock.created {
+            return;
+        }
+        let result = async {
+            self.client
+                .start_request(reqwest::Method::DELETE, &lock.href)
+                .await?
+                .header("if", format!("(<{}>)", lock.token))
+                .send()
+                .await?
+                .dav2xx()
+                .await
+        }
+        .await;
+        match result {
+            Ok(_) => debug!("Removed {} created by the lock", lock.href),
+            Err(e) => warn!("Failed to remove {} created by the lock: {}", lock.href, e),
+        }
+    }
+
+    /// Release a lock. Failures are only logged, the lock expires on its own.
+    pub(super) async fn unlock(&self, lock: Lock) {
+        lock.refresh_handle.abort();
+        let result = async {
+            self.client
+                .start_request(reqwest::Method::from_bytes(b"UNLOCK").unwrap(), &lock.href)
+                .await?
+                .header("lock-token", format!("<{}>", lock.token))
+                .send()
+                .await?
+                .dav2xx()
+                .await
+        }
+        .await;
+        match result {
+            Ok(_) => debug!("Unlocked {}", lock.href),
+            // A MOVE over the locked file may already have released the lock
+            Err(e) => warn!("Failed to unlock {}: {}", lock.href, e),
+        }
+    }
+}
+
+/// Refresh the lock when half of its timeout has passed, at most once a second.
+fn refresh_handle(
+    client: reqwest_dav::Client,
+    href: String,
+    token: String,
+    timeout: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep((timeout / 2).max(Duration::from_secs(1))).await;
+            let result = async {
+                client
+                    .start_request(reqwest::Method::from_bytes(b"LOCK").unwrap(), &href)
+                    .await?
+                    .header("if", format!("(<{}>)", token))
+                    .header("timeout", format!("Second-{}", timeout.as_secs()))
+                    .send()
+                    .await?
+                    .dav2xx()
+                    .await
+            }
+            .await;
+            match result {
+                Ok(_) => debug!("Lock of {} refreshed", href),
+                Err(e) => warn!("Failed to refresh lock of {}: {}", href, e),
+            }
+        }
+    })
+}
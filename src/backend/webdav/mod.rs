@@ -0,0 +1,1513 @@
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use percent_encoding::utf8_percent_encode;
+use reqwest_dav::{
+    list_cmd::{ListMultiStatus, ListProp},
+    re_exports::serde_xml_rs,
+    Auth, Dav2xx as _,
+};
+use snafu::{ResultExt, Snafu};
+use tokio::io::AsyncSeekExt as _;
+use tracing::{debug, warn};
+
+use super::URL_SEGMENT;
+use crate::{path::RelativePath, unique_id, AsyncBufReadSeek, Backend, Metadata, Quota};
+use checksum::Digests;
+
+pub use builder::WebdavBuilder;
+pub use capabilities::{Capabilities, Probe};
+pub use checksum::Checksum;
+pub use chunked::NextcloudChunking;
+pub use partial::PartialUpdate;
+
+mod builder;
+mod capabilities;
+mod checksum;
+mod chunked;
+mod lock;
+mod partial;
+mod stream;
+
+/// What must hold for the uploaded file to replace the one on the server.
+#[derive(Debug)]
+struct Precondition {
+    /// `None` if the file didn't exist before the upload,
+    /// `Some(None)` to overwrite it unconditionally.
+    etag: Option<Option<String>>,
+    /// The token of the lock held on the file.
+    lock_token: Option<String>,
+}
+
+impl Precondition {
+    /// The `If` header for `url`, if any.
+    fn if_header(&self, url: &str) -> Option<String> {
+        let etag = match &self.etag {
+            Some(Some(etag)) if etag.starts_with('"') || etag.starts_with("W/") => {
+                Some(format!("[{}]", etag))
+            }
+            Some(Some(etag)) => Some(format!("[\"{}\"]", etag)),
+            _ => None,
+        };
+        let conditions = self
+            .lock_token
+            .as_ref()
+            .map(|token| format!("<{}>", token))
+            .into_iter()
+            .chain(etag)
+            .collect::<Vec<_>>();
+        (!conditions.is_empty()).then(|| format!("<{}> ({})", url, conditions.join(" ")))
+    }
+}
+
+#[derive(Debug)]
+pub struct Webdav {
+    client: reqwest_dav::Client,
+    /// The absolute folder on the server all paths are relative to
+    folder: PathBuf,
+    check_quota: bool,
+    check_conflict: bool,
+    checksum: Option<Checksum>,
+    verify_etag: bool,
+    chunking: Option<NextcloudChunking>,
+    partial_update: Option<PartialUpdate>,
+    lock_timeout: Option<Duration>,
+    read_buffer_size: usize,
+    probe: Probe,
+    /// What the server supports, detected once
+    capabilities: OnceLock<Capabilities>,
+    /// Collections known to exist on the server
+    collections: Mutex<HashSet<String>>,
+}
+
+impl Webdav {
+    pub async fn new(auth: Auth, url: &str) -> Result<Self, Error> {
+        Self::builder(url).auth(auth).build().await
+    }
+
+    /// Configure authentication, TLS, proxy and timeouts of the client.
+    pub fn builder(url: impl Into<String>) -> WebdavBuilder {
+        WebdavBuilder::new(url)
+    }
+
+    /// Check the available space of the server before uploading a file.
+    pub fn with_quota_check(mut self, check_quota: bool) -> Self {
+        self.check_quota = check_quota;
+        self
+    }
+
+    /// Fail the upload instead of overwriting the file if someone else
+    /// created or changed it while uploading.
+    pub fn with_conflict_check(mut self, check_conflict: bool) -> Self {
+        self.check_conflict = check_conflict;
+        self
+    }
+
+    /// Send a checksum of the file with the upload and compare it with the
+    /// checksum the server returns, if any. Not sent with partial updates.
+    pub fn with_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Compare the ETag returned for an upload with the MD5 of the file,
+    /// for servers using it as ETag like S3-backed gateways.
+    pub fn with_etag_verification(mut self, verify_etag: bool) -> Self {
+        self.verify_etag = verify_etag;
+        self
+    }
+
+    /// Upload files larger than the chunk size with the Nextcloud chunking v2 protocol.
+    pub fn with_nextcloud_chunking(mut self, chunking: NextcloudChunking) -> Self {
+        self.chunking = Some(chunking);
+        self
+    }
+
+    /// Upload files in ranges, continuing from the last acknowledged offset
+    /// when a range fails. Nextcloud chunking takes precedence if both are set.
+    pub fn with_partial_update(mut self, partial_update: PartialUpdate) -> Self {
+        self.partial_update = Some(partial_update);
+        self
+    }
+
+    /// The size of the buffer a file is read with while uploading it with a single PUT.
+    pub fn with_read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = read_buffer_size.max(1);
+        self
+    }
+
+    /// Hold an exclusive write lock on the file while uploading it, so uploads
+    /// of the same path from several instances don't interleave.
+    /// The lock is refreshed before the timeout expires and released afterwards.
+    /// The timeout is at least 5 seconds. Servers without locking support
+    /// (DAV class 2) are uploaded to without a lock.
+    pub fn with_locking(mut self, timeout: Duration) -> Self {
+        self.lock_timeout = Some(timeout.max(lock::MIN_TIMEOUT));
+        self
+    }
+
+    /// Get the quota of the server with the RFC 4331 properties.
+    /// Returns `None` if the server doesn't report them.
+    pub async fn quota(&self) -> Result<Option<Quota>, Error> {
+        let body = r#"<?xml version="1.0" encoding="utf-8" ?>
+            <D:propfind xmlns:D="DAV:">
+                <D:prop>
+                    <D:quota-available-bytes/>
+                    <D:quota-used-bytes/>
+                </D:prop>
+            </D:propfind>
+        "#;
+        let prop = self
+            .propfind(&Self::collection_href(&self.folder), Some(body))
+            .await
+            .context(QuotaSnafu)?;
+
+        // Negative values mean the quota is unknown or unlimited
+        let quota = prop.and_then(|prop| {
+            let used = u64::try_from(prop.quota_used_bytes?).ok()?;
+            let remaining = u64::try_from(prop.quota_available_bytes?).ok()?;
+            Some(Quota {
+                total: used + remaining,
+                used,
+                remaining,
+                deleted: None,
+            })
+        });
+        Ok(quota)
+    }
+
+    /// Get the properties of a single resource, `None` if it doesn't exist.
+    /// body: The PROPFIND request body, all properties if `None`.
+    async fn propfind(
+        &self,
+        href: &str,
+        body: Option<&'static str>,
+    ) -> Result<Option<ListProp>, reqwest_dav::Error> {
+        let response = match body {
+            Some(body) => {
+                self.client
+                    .start_request(reqwest::Method::from_bytes(b"PROPFIND").unwrap(), href)
+                    .await?
+                    .header("depth", "0")
+                    .body(body)
+                    .send()
+                    .await?
+            }
+            None => {
+                self.client
+                    .list_raw(href, reqwest_dav::Depth::Number(0))
+                    .await?
+            }
+        };
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let text = response.dav2xx().await?.text().await?;
+        let status = serde_xml_rs::from_str::<ListMultiStatus>(&text)?;
+
+        Ok(status
+            .responses
+            .into_iter()
+            .flat_map(|response| response.prop_stat)
+            .filter(|prop_stat| prop_stat.status.contains(" 200 "))
+            .map(|prop_stat| prop_stat.prop)
+            .next())
+    }
+
+    /// The percent-encoded absolute path of `path` on the server.
+    fn href(path: &Path) -> String {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => {
+                    Some(utf8_percent_encode(&name.to_string_lossy(), URL_SEGMENT).to_string())
+                }
+                _ => None,
+            })
+            .fold(String::new(), |mut href, name| {
+                href.push('/');
+                href.push_str(&name);
+                href
+            })
+    }
+
+    /// The href of a collection, with a trailing slash.
+    fn collection_href(path: &Path) -> String {
+        format!("{}/", Self::href(path))
+    }
+
+    /// Join a relative upload path onto the folder of the backend.
+    fn validate_path(&self, path: &Path) -> Result<PathBuf, Error> {
+        Ok(RelativePath::new(path)?.to_path(&self.folder))
+    }
+
+    /// The absolute URL of `href`, as required by the `Destination` and `If` headers.
+    fn url(&self, href: &str) -> String {
+        format!("{}{}", self.client.host.trim_end_matches('/'), href)
+    }
+
+    /// A unique, hidden name next to `path` to upload into.
+    fn temp_href(path: &Path) -> String {
+        let name = format!(
+            ".{}.{}.upload",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            unique_id()
+        );
+        Self::href(&path.with_file_name(name))
+    }
+
+    /// Upload with a single PUT next to the file and replace it on success,
+    /// the old file is kept on failure.
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_put(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: &Path,
+        href: &str,
+        precondition: &Precondition,
+        digests: &Digests,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        let temp_href = Self::temp_href(path);
+
+        let read_error = stream::ReadError::default();
+
+        let result = async {
+            let mut request = self
+                .client
+                .start_request(reqwest::Method::PUT, &temp_href)
+                .await
+                .with_context(|_| UploadSnafu { path: href })?
+                // Without it the body is sent with chunked transfer encoding
+                .header(reqwest::header::CONTENT_LENGTH, size)
+                .body(stream::body(
+                    reader,
+                    self.read_buffer_size,
+                    read_error.clone(),
+                ));
+            if let Some(token) = &precondition.lock_token {
+                request = request.header("if", format!("<{}> (<{}>)", self.url(href), token));
+            }
+            let response = match digests
+                .headers(request, self.checksum, metadata)
+                .send()
+                .await
+            {
+                Ok(response) => response,
+                // Report why the body couldn't be read rather than the HTTP error
+                Err(e) => match read_error.take() {
+                    Some(source) => return Err(Error::ReadFile { source }),
+                    None => {
+                        return Err(reqwest_dav::Error::from(e))
+                            .with_context(|_| UploadSnafu { path: href })
+                    }
+                },
+            }
+            .dav2xx()
+            .await
+            .with_context(|_| UploadSnafu { path: href })?;
+            digests.verify(response.headers(), self.verify_etag, href)?;
+
+            let request = self
+                .move_request(&self.client, &temp_href, href, precondition)
+                .await?;
+            Self::send_move(request, href).await
+        }
+        .await;
+
+        if result.is_err() {
+            debug!("Removing temporary file {} after failed upload", temp_href);
+            let _ = self.client.delete(&temp_href).await;
+        }
+        result
+    }
+
+    /// Build a MOVE of `from` onto `href`.
+    async fn move_request(
+        &self,
+        client: &reqwest_dav::Client,
+        from: &str,
+        href: &str,
+        precondition: &Precondition,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let request = client
+            .start_request(reqwest::Method::from_bytes(b"MOVE").unwrap(), from)
+            .await
+            .with_context(|_| MoveSnafu { path: href })?
+            .header("destination", self.url(href));
+        // Locking a missing file creates an empty one, which is ours to replace
+        let overwrite = precondition.etag.is_some() || precondition.lock_token.is_some();
+        let request = request.header("overwrite", if overwrite { "T" } else { "F" });
+        Ok(match precondition.if_header(&self.url(href)) {
+            Some(condition) => request.header("if", condition),
+            None => request,
+        })
+    }
+
+    async fn send_move(request: reqwest::RequestBuilder, href: &str) -> Result<(), Error> {
+        let response = request
+            .send()
+            .await
+            .map_err(reqwest_dav::Error::from)
+            .with_context(|_| MoveSnafu { path: href })?;
+        if response.status() == reqwest::StatusCode::PRECONDITION_FAILED {
+            return Err(Error::Conflict {
+                path: href.to_string(),
+            });
+        }
+        response
+            .dav2xx()
+            .await
+            .with_context(|_| MoveSnafu { path: href })?;
+        Ok(())
+    }
+
+    /// Upload with Nextcloud chunking or partial updates if configured and
+    /// supported, a single PUT otherwise.
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_with_strategy(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: &Path,
+        href: &str,
+        precondition: &Precondition,
+        digests: &Digests,
+        metadata: &Metadata,
+    ) -> Result<(), Error> {
+        if let Some(chunking) = &self.chunking {
+            if size > chunking.chunk_size {
+                return self
+                    .upload_chunked(
+                        reader,
+                        size,
+                        href,
+                        precondition,
+                        chunking,
+                        digests,
+                        metadata,
+                    )
+                    .await;
+            }
+        }
+        if let Some(mode) = self.partial_update {
+            if size > partial::CHUNK_SIZE {
+                if let Some(method) = self.partial_update_method(mode).await? {
+                    return self
+                        .upload_partial(reader, size, path, href, precondition, method)
+                        .await;
+                }
+            }
+        }
+        self.upload_put(reader, size, path, href, precondition, digests, metadata)
+            .await
+    }
+
+    /// Create every missing collection above `path`, like `create_dir_all`.
+    async fn create_parent_collections(&self, path: &Path) -> Result<(), Error> {
+        let Some(parent) = path.parent() else {
+            return Ok(());
+        };
+
+        let mut collection = String::from("/");
+        for component in parent.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            collection
+                .push_str(&utf8_percent_encode(&name.to_string_lossy(), URL_SEGMENT).to_string());
+            collection.push('/');
+
+            if self.collections.lock().unwrap().contains(&collection) {
+                continue;
+            }
+            if !self.collection_exists(&collection).await? {
+                let response = self.client.mkcol_raw(&collection).await.with_context(|_| {
+                    CreateCollectionSnafu {
+                        path: collection.clone(),
+                    }
+                })?;
+                // 405 Method Not Allowed: the collection was created in the meantime
+                if response.status() != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+                    response
+                        .dav2xx()
+                        .await
+                        .with_context(|_| CreateCollectionSnafu {
+                            path: collection.clone(),
+                        })?;
+                }
+            }
+            self.collections.lock().unwrap().insert(collection.clone());
+        }
+        Ok(())
+    }
+
+    /// Drop the collections above `href` from the cache, someone may have
+    /// deleted them on the server.
+    fn forget_collections(&self, href: &str) {
+        self.collections
+            .lock()
+            .unwrap()
+            .retain(|collection| !href.starts_with(collection.as_str()));
+    }
+
+    async fn collection_exists(&self, path: &str) -> Result<bool, Error> {
+        let response = self
+            .client
+            .list_raw(path, reqwest_dav::Depth::Number(0))
+            .await
+            .with_context(|_| CreateCollectionSnafu { path })?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response
+            .dav2xx()
+            .await
+            .with_context(|_| CreateCollectionSnafu { path })?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl Backend for Webdav {
+    async fn upload(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: PathBuf,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        self.upload_with_metadata(reader, size, path, Metadata::default())
+            .await
+    }
+
+    async fn upload_with_metadata(
+        &self,
+        mut reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: PathBuf,
+        metadata: Metadata,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        debug!("Uploading file to webdav: {:?}", &path);
+        let path = self.validate_path(&path)?;
+
+        // Skip the request if the probe found the server doesn't report a quota
+        let quota_supported = self.capabilities.get().map_or(true, |c| c.quota);
+        if self.check_quota && quota_supported {
+            if let Some(quota) = self.quota().await? {
+                if quota.remaining < size {
+                    return Err(Error::InsufficientStorage {
+                        path: path.to_string_lossy().to_string(),
+                        size,
+                        remaining: quota.remaining,
+                    }
+                    .into());
+                }
+            }
+        }
+
+        self.create_parent_collections(&path).await?;
+
+        let href = Self::href(&path);
+        let etag = if self.check_conflict {
+            self.propfind(&href, None)
+                .await
+                .with_context(|_| UploadSnafu { path: &href })?
+                .map(|prop| prop.tag)
+        } else {
+            Some(None)
+        };
+
+        let digests = Digests::compute(
+            &mut reader,
+            self.checksum == Some(Checksum::Md5) || self.verify_etag,
+            self.checksum == Some(Checksum::Sha1),
+        )
+        .await?;
+
+        let lock = match self.lock_timeout {
+            Some(timeout) if self.capabilities().await?.locking => {
+                Some(self.lock(&href, timeout).await?)
+            }
+            Some(_) => {
+                warn!(
+                    "The server doesn't support locking, uploading {} unlocked",
+                    href
+                );
+                None
+            }
+            None => None,
+        };
+        let precondition = Precondition {
+            etag,
+            lock_token: lock.as_ref().map(|lock| lock.token.clone()),
+        };
+
+        // Kept to upload again if a parent collection is gone
+        let reader = stream::SharedReader::new(reader);
+        let mut result = self
+            .upload_with_strategy(
+                Box::new(reader.clone()),
+                size,
+                &path,
+                &href,
+                &precondition,
+                &digests,
+                &metadata,
+            )
+            .await;
+        if result.as_ref().is_err_and(Error::is_missing_parent) {
+            debug!("Creating the parent collections of {} again", href);
+            self.forget_collections(&href);
+            result = async {
+                self.create_parent_collections(&path).await?;
+                reader
+                    .clone()
+                    .seek(tokio::io::SeekFrom::Start(0))
+                    .await
+                    .context(ReadFileSnafu)?;
+                self.upload_with_strategy(
+                    Box::new(reader),
+                    size,
+                    &path,
+                    &href,
+                    &precondition,
+                    &digests,
+                    &metadata,
+                )
+                .await
+            }
+            .await;
+        }
+        if let Some(lock) = lock {
+            if result.is_err() {
+                self.remove_created(&lock).await;
+            }
+            self.unlock(lock).await;
+        }
+        Ok(result?)
+    }
+
+    async fn quota(&self) -> Result<Option<Quota>, Box<dyn snafu::Error>> {
+        Ok(Webdav::quota(self).await?)
+    }
+}
+
+#[derive(Snafu, Debug)]
+pub enum Error {
+    #[snafu(display("Failed to build webdav client: {}", source))]
+    BuildClient { source: reqwest_dav::Error },
+
+    #[snafu(display("Failed to build http client: {}", source))]
+    BuildHttpClient { source: reqwest::Error },
+
+    #[snafu(display("Invalid header {}", name))]
+    InvalidHeader { name: String },
+
+    #[snafu(display("Invalid certificate: {}", source))]
+    InvalidCertificate { source: reqwest::Error },
+
+    #[snafu(display("Invalid proxy: {}", source))]
+    InvalidProxy { source: reqwest::Error },
+
+    #[snafu(display("Failed to probe {}: {}", path, source))]
+    Probe {
+        source: reqwest_dav::Error,
+        path: String,
+    },
+
+    #[snafu(display("Failed to list files: {}", source))]
+    ListFiles { source: reqwest_dav::Error },
+
+    #[snafu(transparent)]
+    Path { source: crate::path::Error },
+
+    #[snafu(display("Failed to upload file {}: {}", path, source))]
+    Upload {
+        source: reqwest_dav::Error,
+        path: String,
+    },
+
+    #[snafu(display("Failed to move uploaded file to {}: {}", path, source))]
+    Move {
+        source: reqwest_dav::Error,
+        path: String,
+    },
+
+    #[snafu(display("File {} was changed by someone else during the upload", path))]
+    Conflict { path: String },
+
+    #[snafu(display("Failed to lock {}: {}", path, source))]
+    Lock {
+        source: reqwest_dav::Error,
+        path: String,
+    },
+
+    #[snafu(display("File {} is locked by someone else", path))]
+    Locked { path: String },
+
+    #[snafu(display("The server locked {} without returning a lock token", path))]
+    MissingLockToken { path: String },
+
+    /// The file to upload couldn't be read, as opposed to a failed request.
+    #[snafu(display("Failed to read file: {}", source))]
+    ReadFile { source: std::io::Error },
+
+    #[snafu(display(
+        "Checksum mismatch for {}: expected {}, the server reported {}",
+        path,
+        expected,
+        actual
+    ))]
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[snafu(display("Failed to upload chunk {} of {}: {}", chunk, path, source))]
+    UploadChunk {
+        source: reqwest_dav::Error,
+        path: String,
+        chunk: u64,
+    },
+
+    #[snafu(display("Failed to upload {}, chunks {:?} are still missing", path, chunks))]
+    MissingChunks { path: String, chunks: Vec<u64> },
+
+    #[snafu(display("Failed to create collection {}: {}", path, source))]
+    CreateCollection {
+        source: reqwest_dav::Error,
+        path: String,
+    },
+
+    #[snafu(display("Failed to get quota: {}", source))]
+    Quota { source: reqwest_dav::Error },
+
+    #[snafu(display(
+        "Not enough space for {path}: {size} bytes required, {remaining} bytes remaining"
+    ))]
+    InsufficientStorage {
+        path: String,
+        size: u64,
+        remaining: u64,
+    },
+}
+
+impl Error {
+    /// Whether a PUT or MOVE failed with 409 Conflict, which servers answer
+    /// when a parent collection is missing.
+    fn is_missing_parent(&self) -> bool {
+        match self {
+            Error::Upload { source, .. } | Error::Move { source, .. } => matches!(
+                source,
+                reqwest_dav::Error::Decode(reqwest_dav::DecodeError::Server(
+                    reqwest_dav::ServerError {
+                        response_code: 409,
+                        ..
+                    }
+                ))
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether uploading again can't complete the upload, so what was already
+    /// uploaded is removed instead of kept for the next attempt.
+    fn abandons_upload(&self) -> bool {
+        matches!(
+            self,
+            Error::Conflict { .. } | Error::ChecksumMismatch { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use dav_server::{body::Body, memfs::MemFs, memls::MemLs, DavHandler};
+    use hyper::{server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Serve an in-memory WebDAV server, returning its URL.
+    async fn serve() -> String {
+        serve_with(|handler, req| async move { handler.handle(req).await }).await
+    }
+
+    /// Like [`serve`], passing every request through `service` to mock
+    /// other servers.
+    async fn serve_with<S, F>(service: S) -> String
+    where
+        S: Fn(DavHandler, hyper::Request<hyper::body::Incoming>) -> F
+            + Clone
+            + Send
+            + Sync
+            + 'static,
+        F: std::future::Future<Output = hyper::Response<Body>> + Send + 'static,
+    {
+        let handler = DavHandler::builder()
+            .filesystem(MemFs::new())
+            .locksystem(MemLs::new())
+            .build_handler();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let (handler, service) = (handler.clone(), service.clone());
+                tokio::spawn(async move {
+                    let service = service_fn(move |req| {
+                        let response = service(handler.clone(), req);
+                        async move { Ok::<_, Infallible>(response.await) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        url
+    }
+
+    fn response(status: hyper::StatusCode) -> hyper::Response<Body> {
+        let mut response = hyper::Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+
+    /// Send a request to the WebDAV handler directly.
+    async fn handle(handler: &DavHandler, method: &str, path: &str, body: Body) -> Vec<u8> {
+        let request = hyper::Request::builder()
+            .method(method)
+            .uri(path)
+            .body(body)
+            .unwrap();
+        let response = handler.handle(request).await;
+        if !response.status().is_success() {
+            return Vec::new();
+        }
+        let mut body = response.into_body();
+        let mut content = Vec::new();
+        while let Some(frame) =
+            std::future::poll_fn(|cx| hyper::body::Body::poll_frame(Pin::new(&mut body), cx)).await
+        {
+            if let Ok(data) = frame.unwrap().into_data() {
+                content.extend_from_slice(&data);
+            }
+        }
+        content
+    }
+
+    #[tokio::test]
+    async fn test_upload_nested() {
+        let url = serve().await;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_conflict_check(true);
+
+        for content in [b"first".to_vec(), b"second".to_vec()] {
+            let size = content.len() as u64;
+            webdav
+                .upload(
+                    Box::new(std::io::Cursor::new(content)),
+                    size,
+                    "backups/2026/10/db.tar".into(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let body = webdav
+            .client
+            .get("/backups/2026/10/db.tar")
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"second");
+
+        // No temporary file is left behind
+        let entries = webdav
+            .client
+            .list("/backups/2026/10/", reqwest_dav::Depth::Number(1))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // Collections deleted by someone else are created again
+        webdav.client.delete("/backups/2026/").await.unwrap();
+        webdav
+            .upload(
+                Box::new(std::io::Cursor::new(b"third".to_vec())),
+                5,
+                "backups/2026/10/db.tar".into(),
+            )
+            .await
+            .unwrap();
+        let body = webdav.client.get("/backups/2026/10/db.tar").await.unwrap();
+        assert_eq!(body.bytes().await.unwrap().as_ref(), b"third");
+    }
+
+    #[tokio::test]
+    async fn test_upload_partial() {
+        let url = serve().await;
+        let content: Vec<u8> = (0..partial::CHUNK_SIZE * 2 + 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+
+        for mode in [PartialUpdate::Auto, PartialUpdate::ContentRange] {
+            let webdav = Webdav::new(Auth::Anonymous, &url)
+                .await
+                .unwrap()
+                .with_partial_update(mode);
+            webdav
+                .upload(
+                    Box::new(std::io::Cursor::new(content.clone())),
+                    content.len() as u64,
+                    "large.bin".into(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                webdav
+                    .capabilities
+                    .get()
+                    .map(|capabilities| capabilities.partial_update),
+                Some(true)
+            );
+
+            let body = webdav
+                .client
+                .get("/large.bin")
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert!(body.as_ref() == content.as_slice());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partial_resume() {
+        let ranges = Arc::new(Mutex::new(Vec::<u64>::new()));
+        let recorded = ranges.clone();
+        let url = serve_with(move |handler, req| {
+            let ranges = recorded.clone();
+            async move {
+                if req.uri().path().ends_with(".partial") {
+                    let start_pos = req
+                        .headers()
+                        .get("x-update-range")
+                        .map(|range| range.to_str().unwrap())
+                        .and_then(|range| range.strip_prefix("bytes="))
+                        .map_or(0, |range| range.split('-').next().unwrap().parse().unwrap());
+                    let mut ranges = ranges.lock().unwrap();
+                    if matches!(req.method().as_str(), "PUT" | "PATCH") {
+                        ranges.push(start_pos);
+                        // The second range fails during the whole first upload
+                        if start_pos > 0 && ranges.iter().filter(|&&pos| pos > 0).count() <= 3 {
+                            return response(hyper::StatusCode::INTERNAL_SERVER_ERROR);
+                        }
+                    }
+                }
+                handler.handle(req).await
+            }
+        })
+        .await;
+
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_partial_update(PartialUpdate::SabreDav);
+        let content: Vec<u8> = (0..partial::CHUNK_SIZE * 2 + 3)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let upload = || {
+            webdav.upload(
+                Box::new(std::io::Cursor::new(content.clone())),
+                content.len() as u64,
+                "large.bin".into(),
+            )
+        };
+        assert!(upload().await.is_err());
+        // The temporary file is kept for the next attempt
+        let entries = webdav
+            .client
+            .list("/", reqwest_dav::Depth::Number(1))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+
+        upload().await.unwrap();
+        let body = webdav.client.get("/large.bin").await.unwrap();
+        assert!(body.bytes().await.unwrap().as_ref() == content.as_slice());
+        // The second upload starts at the failed range
+        let chunk = partial::CHUNK_SIZE;
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [0, chunk, chunk, chunk, chunk, chunk * 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upload_with_metadata() {
+        let headers = Arc::new(Mutex::new(hyper::HeaderMap::new()));
+        let recorded = headers.clone();
+        let url = serve_with(move |handler, req| {
+            let headers = recorded.clone();
+            async move {
+                if req.method() == hyper::Method::PUT {
+                    *headers.lock().unwrap() = req.headers().clone();
+                }
+                handler.handle(req).await
+            }
+        })
+        .await;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_checksum(Checksum::Md5);
+
+        let metadata = Metadata {
+            modified: Some(std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            ..Default::default()
+        };
+        webdav
+            .upload_with_metadata(
+                Box::new(std::io::Cursor::new(b"content".to_vec())),
+                7,
+                "file.txt".into(),
+                metadata,
+            )
+            .await
+            .unwrap();
+
+        let body = webdav
+            .client
+            .get("/file.txt")
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"content");
+
+        let headers = headers.lock().unwrap();
+        assert_eq!(headers["x-oc-mtime"], "1700000000");
+        assert_eq!(
+            headers["oc-checksum"],
+            "MD5:9a0364b9e99bb480dd25e1f0284c8555"
+        );
+        assert_eq!(headers["content-md5"], "mgNkuembtIDdJeHwKEyFVQ==");
+    }
+
+    #[tokio::test]
+    async fn test_checksum_mismatch() {
+        // The server stores something else than what was sent
+        let url = serve_with(|handler, req| async move {
+            let is_put = req.method() == hyper::Method::PUT;
+            let mut response = handler.handle(req).await;
+            if is_put {
+                response.headers_mut().insert(
+                    "oc-checksum",
+                    hyper::header::HeaderValue::from_static("MD5:00000000000000000000000000000000"),
+                );
+            }
+            response
+        })
+        .await;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_checksum(Checksum::Md5);
+
+        let error = webdav
+            .upload(
+                Box::new(std::io::Cursor::new(b"content".to_vec())),
+                7,
+                "file.txt".into(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::ChecksumMismatch { .. })
+        ));
+        // Neither the file nor the temporary file is left
+        let entries = webdav
+            .client
+            .list("/", reqwest_dav::Depth::Number(1))
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_builder() {
+        let url = serve().await;
+        let webdav = Webdav::builder(&url)
+            .bearer_token("token")
+            .header("x-client", "upload-backend")
+            .timeout(std::time::Duration::from_secs(10))
+            .http1_only()
+            .build()
+            .await
+            .unwrap();
+        webdav
+            .upload(Box::new(std::io::Cursor::new(b"a".to_vec())), 1, "a".into())
+            .await
+            .unwrap();
+
+        let result = Webdav::builder(&url).header("x-bad", "\n").build().await;
+        assert!(matches!(result, Err(Error::InvalidHeader { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_capabilities() {
+        let url = serve().await;
+        let webdav = Webdav::builder(&url)
+            .probe(Probe::None)
+            .build()
+            .await
+            .unwrap();
+        assert!(webdav.capabilities.get().is_none());
+
+        let capabilities = webdav.capabilities().await.unwrap();
+        assert!(capabilities.locking);
+        assert!(capabilities.partial_update);
+        assert!(!capabilities.nextcloud_chunking);
+        assert!(webdav.chunking.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_folder() {
+        let url = serve().await;
+        let webdav = Webdav::builder(&url)
+            .folder("backups/db")
+            .probe(Probe::None)
+            .build()
+            .await
+            .unwrap();
+        webdav
+            .upload(
+                Box::new(std::io::Cursor::new(b"a".to_vec())),
+                1,
+                "2026/a.txt".into(),
+            )
+            .await
+            .unwrap();
+        let body = webdav.client.get("/backups/db/2026/a.txt").await.unwrap();
+        assert_eq!(body.bytes().await.unwrap().as_ref(), b"a");
+
+        for path in ["/etc/passwd", "../a.txt", "2026/../../a.txt", ""] {
+            assert!(matches!(
+                webdav.validate_path(Path::new(path)),
+                Err(Error::Path { .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locking() {
+        let url = serve().await;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_conflict_check(true)
+            .with_locking(Duration::from_secs(60));
+        // Sub-second timeouts would be sent as `Second-0`
+        let short = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_locking(Duration::from_millis(500));
+        assert_eq!(short.lock_timeout, Some(lock::MIN_TIMEOUT));
+
+        for content in [b"first".to_vec(), b"second".to_vec()] {
+            let size = content.len() as u64;
+            webdav
+                .upload(
+                    Box::new(std::io::Cursor::new(content)),
+                    size,
+                    "a.txt".into(),
+                )
+                .await
+                .unwrap();
+        }
+        let body = webdav.client.get("/a.txt").await.unwrap();
+        assert_eq!(body.bytes().await.unwrap().as_ref(), b"second");
+
+        // Another instance holds the lock
+        let lock = webdav
+            .lock("/a.txt", Duration::from_secs(60))
+            .await
+            .unwrap();
+        let result = webdav
+            .upload(
+                Box::new(std::io::Cursor::new(b"third".to_vec())),
+                5,
+                "a.txt".into(),
+            )
+            .await;
+        assert!(result.unwrap_err().to_string().contains("locked"));
+        webdav.unlock(lock).await;
+
+        // The file the lock created isn't left behind
+        let reader = FailingReader {
+            pos: 0,
+            fail_at: 1024,
+        };
+        let result = webdav.upload(Box::new(reader), 4096, "b.txt".into()).await;
+        assert!(result.is_err());
+        assert!(webdav.propfind("/b.txt", None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_locking_unsupported() {
+        // A class 1 server
+        let url = serve_with(|handler, req| async move {
+            match req.method().as_str() {
+                "LOCK" => response(hyper::StatusCode::METHOD_NOT_ALLOWED),
+                _ => {
+                    let mut response = handler.handle(req).await;
+                    if response.headers().contains_key("dav") {
+                        response
+                            .headers_mut()
+                            .insert("dav", hyper::header::HeaderValue::from_static("1"));
+                    }
+                    response
+                }
+            }
+        })
+        .await;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_locking(Duration::from_secs(60));
+        webdav
+            .upload(
+                Box::new(std::io::Cursor::new(b"content".to_vec())),
+                7,
+                "a.txt".into(),
+            )
+            .await
+            .unwrap();
+
+        // A lock without a token can't be used
+        let url = serve_with(|handler, req| async move {
+            match req.method().as_str() {
+                "LOCK" => response(hyper::StatusCode::OK),
+                _ => handler.handle(req).await,
+            }
+        })
+        .await;
+        let webdav = Webdav::new(Auth::Anonymous, &url).await.unwrap();
+        assert!(matches!(
+            webdav.lock("/a.txt", Duration::from_secs(60)).await,
+            Err(Error::MissingLockToken { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_move_failure() {
+        for status in [
+            hyper::StatusCode::PRECONDITION_FAILED,
+            hyper::StatusCode::CONFLICT,
+        ] {
+            let url = serve_with(move |handler, req| async move {
+                match req.method().as_str() {
+                    "MOVE" => response(status),
+                    _ => handler.handle(req).await,
+                }
+            })
+            .await;
+            let webdav = Webdav::new(Auth::Anonymous, &url).await.unwrap();
+            webdav
+                .client
+                .put("/a.txt", b"first".to_vec())
+                .await
+                .unwrap();
+
+            let error = webdav
+                .upload(
+                    Box::new(std::io::Cursor::new(b"second".to_vec())),
+                    6,
+                    "a.txt".into(),
+                )
+                .await
+                .unwrap_err();
+            match (status, error.downcast_ref::<Error>()) {
+                (hyper::StatusCode::PRECONDITION_FAILED, Some(Error::Conflict { .. })) => {}
+                (hyper::StatusCode::CONFLICT, Some(Error::Move { .. })) => {}
+                _ => panic!("unexpected error for {}: {}", status, error),
+            }
+
+            // The target is unchanged and the temporary file is removed
+            let body = webdav.client.get("/a.txt").await.unwrap();
+            assert_eq!(body.bytes().await.unwrap().as_ref(), b"first");
+            let entries = webdav
+                .client
+                .list("/", reqwest_dav::Depth::Number(1))
+                .await
+                .unwrap();
+            assert_eq!(entries.len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_resume() {
+        let puts = Arc::new(Mutex::new(Vec::<String>::new()));
+        let move_headers = Arc::new(Mutex::new(hyper::HeaderMap::new()));
+        let recorded = (puts.clone(), move_headers.clone());
+        let url = serve_with(move |handler, req| {
+            let (puts, move_headers) = recorded.clone();
+            async move {
+                let path = req.uri().path().to_string();
+                match req.method().as_str() {
+                    "PUT" if path.starts_with("/uploads/") => {
+                        let mut puts = puts.lock().unwrap();
+                        puts.push(path.clone());
+                        // The second chunk fails during the whole first upload
+                        if path.ends_with("/00002")
+                            && puts.iter().filter(|p| **p == path).count() <= 3
+                        {
+                            return response(hyper::StatusCode::INTERNAL_SERVER_ERROR);
+                        }
+                    }
+                    // Assemble the chunks like Nextcloud
+                    "MOVE" if path.ends_with("/.file") => {
+                        *move_headers.lock().unwrap() = req.headers().clone();
+                        let transfer = path.trim_end_matches("/.file");
+                        let mut content = Vec::new();
+                        for chunk in 1..=3 {
+                            let chunk = format!("{}/{:05}", transfer, chunk);
+                            content.extend(handle(&handler, "GET", &chunk, Body::empty()).await);
+                        }
+                        let destination = req.headers()["destination"].to_str().unwrap();
+                        let destination = destination.parse::<hyper::Uri>().unwrap();
+                        let content = Body::from(hyper::body::Bytes::from(content));
+                        handle(&handler, "PUT", destination.path(), content).await;
+                        handle(&handler, "DELETE", transfer, Body::empty()).await;
+                        return response(hyper::StatusCode::CREATED);
+                    }
+                    _ => {}
+                }
+                handler.handle(req).await
+            }
+        })
+        .await;
+
+        let mut chunking = NextcloudChunking::new(format!("{}/uploads", url));
+        chunking.chunk_size = 1024;
+        let webdav = Webdav::new(Auth::Anonymous, &url)
+            .await
+            .unwrap()
+            .with_nextcloud_chunking(chunking)
+            .with_checksum(Checksum::Md5);
+        webdav.client.mkcol("/uploads").await.unwrap();
+
+        let content: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+        let error = webdav
+            .upload(
+                Box::new(std::io::Cursor::new(content.clone())),
+                content.len() as u64,
+                "resume.bin".into(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::MissingChunks { .. })
+        ));
+        // The transfer is kept for the next attempt
+        let transfers = webdav
+            .client
+            .list("/uploads/", reqwest_dav::Depth::Number(1))
+            .await
+            .unwrap();
+        assert_eq!(transfers.len(), 2);
+
+        let metadata = Metadata {
+            modified: Some(std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            ..Default::default()
+        };
+        webdav
+            .upload_with_metadata(
+                Box::new(std::io::Cursor::new(content.clone())),
+                content.len() as u64,
+                "resume.bin".into(),
+                metadata,
+            )
+            .await
+            .unwrap();
+        // The checksum and mtime of the assembled file go with the MOVE
+        let headers = move_headers.lock().unwrap().clone();
+        assert!(headers["oc-checksum"].to_str().unwrap().starts_with("MD5:"));
+        assert_eq!(headers["x-oc-mtime"], "1700000000");
+        assert!(!headers.contains_key("content-md5"));
+
+        let body = webdav.client.get("/resume.bin").await.unwrap();
+        assert!(body.bytes().await.unwrap().as_ref() == content.as_slice());
+        // Only the failed chunk is uploaded again, also by the second upload
+        let chunks = puts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|path| path.rsplit('/').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            ["00001", "00002", "00003", "00002", "00002", "00002"]
+        );
+    }
+
+    /// Yields zeros and fails after `fail_at` bytes.
+    struct FailingReader {
+        pos: u64,
+        fail_at: u64,
+    }
+
+    impl tokio::io::AsyncRead for FailingReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            if self.pos >= self.fail_at {
+                return std::task::Poll::Ready(Err(std::io::Error::other("disk on fire")));
+            }
+            let len = buf.remaining().min((self.fail_at - self.pos) as usize);
+            buf.put_slice(&vec![0; len]);
+            self.pos += len as u64;
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl tokio::io::AsyncSeek for FailingReader {
+        fn start_seek(
+            mut self: std::pin::Pin<&mut Self>,
+            position: std::io::SeekFrom,
+        ) -> std::io::Result<()> {
+            if let std::io::SeekFrom::Start(pos) = position {
+                self.pos = pos;
+            }
+            Ok(())
+        }
+
+        fn poll_complete(
+            self: std::pin::Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<u64>> {
+            std::task::Poll::Ready(Ok(self.pos))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_error() {
+        let url = serve().await;
+        let webdav = Webdav::new(Auth::Anonymous, &url).await.unwrap();
+
+        let reader = FailingReader {
+            pos: 0,
+            fail_at: 1024 * 1024,
+        };
+        let error = webdav
+            .upload(Box::new(reader), 4 * 1024 * 1024, "broken.bin".into())
+            .await
+            .unwrap_err();
+        match error.downcast_ref::<Error>() {
+            Some(Error::ReadFile { source }) => assert_eq!(source.to_string(), "disk on fire"),
+            _ => panic!("unexpected error: {}", error),
+        }
+        assert!(webdav
+            .propfind("/broken.bin", None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    /// Records the largest read of the inner reader.
+    struct RecordingReader {
+        inner: std::io::Cursor<Vec<u8>>,
+        largest_read: Arc<Mutex<usize>>,
+    }
+
+    impl tokio::io::AsyncRead for RecordingReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let mut largest_read = self.largest_read.lock().unwrap();
+            *largest_read = (*largest_read).max(buf.remaining());
+            drop(largest_read);
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl tokio::io::AsyncSeek for RecordingReader {
+        fn start_seek(
+            mut self: Pin<&mut Self>,
+            position: std::io::SeekFrom,
+        ) -> std::io::Result<()> {
+            Pin::new(&mut self.inner).start_seek(position)
+        }
+
+        fn poll_complete(
+            mut self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<u64>> {
+            Pin::new(&mut self.inner).poll_complete(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_read_buffer_size() {
+        let url = serve().await;
+        let content: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+
+        for buffer_size in [4 * 1024, 64 * 1024] {
+            let webdav = Webdav::new(Auth::Anonymous, &url)
+                .await
+                .unwrap()
+                .with_read_buffer_size(buffer_size);
+            let largest_read = Arc::new(Mutex::new(0));
+            let reader = RecordingReader {
+                inner: std::io::Cursor::new(content.clone()),
+                largest_read: largest_read.clone(),
+            };
+            webdav
+                .upload(Box::new(reader), content.len() as u64, "large.bin".into())
+                .await
+                .unwrap();
+            // The file is streamed, never read at once
+            let largest_read = *largest_read.lock().unwrap();
+            assert!(largest_read > 0 && largest_read <= buffer_size);
+
+            let body = webdav
+                .client
+                .get("/large.bin")
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            assert!(body.as_ref() == content.as_slice());
+        }
+    }
+}
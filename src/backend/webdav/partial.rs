@@ -0,0 +1,215 @@
+use std::path::Path;
+
+use reqwest_dav::Dav2xx as _;
+use snafu::ResultExt;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+use tracing::{debug, warn};
+
+use crate::AsyncBufReadSeek;
+
+use super::{
+    stream::{self, SharedReader},
+    Error, Precondition, ReadFileSnafu, UploadChunkSnafu, UploadSnafu, Webdav,
+};
+
+/// The size of each range sent with a partial update.
+/// 10 MB
+pub(super) const CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+/// How many times in a row a range may fail before giving up.
+const MAX_ATTEMPTS: usize = 3;
+/// The content type sabre/dav expects for `PATCH` requests.
+const SABREDAV_PARTIAL_UPDATE: &str = "application/x-sabredav-partialupdate";
+
+/// How to upload a file in ranges, so an interrupted upload continues
+/// from the last acknowledged offset instead of starting over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialUpdate {
+    /// Use sabre/dav partial updates if the server announces them,
+    /// a single PUT otherwise.
+    Auto,
+    /// `PATCH` with `X-Update-Range`, supported by sabre/dav based servers.
+    SabreDav,
+    /// `PUT` with `Content-Range`, supported by Apache mod_dav.
+    ContentRange,
+}
+
+impl Webdav {
+    /// The partial update method to use, `None` if the server doesn't support any.
+    pub(super) async fn partial_update_method(
+        &self,
+        mode: PartialUpdate,
+    ) -> Result<Option<PartialUpdate>, Error> {
+        match mode {
+            PartialUpdate::Auto => Ok(self
+                .capabilities()
+                .await?
+                .partial_update
+                .then_some(PartialUpdate::SabreDav)),
+            mode => Ok(Some(mode)),
+        }
+    }
+
+    /// Upload next to the file in ranges and replace it on success.
+    /// The temporary file is kept when the upload fails, uploading the same
+    /// file again continues at its length.
+    pub(super) async fn upload_partial(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: &Path,
+        href: &str,
+        precondition: &Precondition,
+        method: PartialUpdate,
+    ) -> Result<(), Error> {
+        let temp_href = partial_href(path, size);
+
+        let result = async {
+            let reader = SharedReader::new(reader);
+            let start_pos = self.uploaded_length(&temp_href, href, size).await?;
+            if start_pos > 0 {
+                debug!("Continuing upload of {} at {}", href, start_pos);
+            }
+            self.upload_ranges(&reader, size, start_pos, &temp_href, href, method)
+                .await?;
+            let request = self
+                .move_request(&self.client, &temp_href, href, precondition)
+                .await?;
+            Self::send_move(request, href).await
+        }
+        .await;
+
+        if result.as_ref().is_err_and(Error::abandons_upload) {
+            debug!("Removing temporary file {} after failed upload", temp_href);
+            let _ = self.client.delete(&temp_href).await;
+        }
+        result
+    }
+
+    /// How much of the temporary file of a file of `size` bytes the server stored.
+    async fn uploaded_length(&self, temp_href: &str, href: &str, size: u64) -> Result<u64, Error> {
+        Ok(self
+            .propfind(temp_href, None)
+            .await
+            .with_context(|_| UploadSnafu { path: href })?
+            .and_then(|prop| prop.content_length)
+            .map_or(0, |len| (len.max(0) as u64).min(size)))
+    }
+
+    async fn upload_ranges(
+        &self,
+        reader: &SharedReader,
+        size: u64,
+        mut start_pos: u64,
+        temp_href: &str,
+        href: &str,
+        method: PartialUpdate,
+    ) -> Result<(), Error> {
+        let mut failures = 0;
+
+        while start_pos < size {
+            let len = CHUNK_SIZE.min(size - start_pos);
+
+            match self
+                .upload_range(reader, temp_href, href, start_pos, len, size, method)
+                .await
+            {
+                Ok(()) => {
+                    start_pos += len;
+                    failures = 0;
+                }
+                // The source can't be read, retrying won't help
+                Err(e @ Error::ReadFile { .. }) => return Err(e),
+                Err(e) => {
+                    failures += 1;
+                    if failures >= MAX_ATTEMPTS {
+                        return Err(e);
+                    }
+                    warn!("{}", e);
+                    // Continue from what the server actually stored
+                    start_pos = self.uploaded_length(temp_href, href, size).await?;
+                    debug!("Continuing upload of {} at {}", href, start_pos);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_range(
+        &self,
+        reader: &SharedReader,
+        temp_href: &str,
+        href: &str,
+        start_pos: u64,
+        len: u64,
+        size: u64,
+        method: PartialUpdate,
+    ) -> Result<(), Error> {
+        let chunk = start_pos / CHUNK_SIZE + 1;
+        let end_pos = start_pos + len - 1;
+
+        // The first range creates the file
+        let method_name = match method {
+            _ if start_pos == 0 => reqwest::Method::PUT,
+            PartialUpdate::ContentRange => reqwest::Method::PUT,
+            _ => reqwest::Method::PATCH,
+        };
+        let request = self
+            .client
+            .start_request(method_name, temp_href)
+            .await
+            .with_context(|_| UploadChunkSnafu { path: href, chunk })?;
+        let request = match method {
+            _ if start_pos == 0 => request,
+            PartialUpdate::ContentRange => request.header(
+                "content-range",
+                format!("bytes {}-{}/{}", start_pos, end_pos, size),
+            ),
+            _ => request
+                .header("content-type", SABREDAV_PARTIAL_UPDATE)
+                .header("x-update-range", format!("bytes={}-{}", start_pos, end_pos)),
+        };
+
+        reader
+            .clone()
+            .seek(tokio::io::SeekFrom::Start(start_pos))
+            .await
+            .context(ReadFileSnafu)?;
+        let read_error = stream::ReadError::default();
+        let response = match request
+            .header(reqwest::header::CONTENT_LENGTH, len)
+            .body(stream::body(
+                reader.clone().take(len),
+                self.read_buffer_size,
+                read_error.clone(),
+            ))
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => match read_error.take() {
+                Some(source) => return Err(Error::ReadFile { source }),
+                None => {
+                    return Err(reqwest_dav::Error::from(e))
+                        .with_context(|_| UploadChunkSnafu { path: href, chunk })
+                }
+            },
+        };
+        response
+            .dav2xx()
+            .await
+            .with_context(|_| UploadChunkSnafu { path: href, chunk })?;
+        Ok(())
+    }
+}
+
+/// The hidden name next to `path` a file of `size` bytes is uploaded into.
+/// It only depends on the upload, so a later attempt finds the ranges of a failed one.
+fn partial_href(path: &Path, size: u64) -> String {
+    let name = format!(
+        ".{}.{}.partial",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        size
+    );
+    Webdav::href(&path.with_file_name(name))
+}
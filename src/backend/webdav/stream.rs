@@ -0,0 +1,95 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use tokio_util::io::ReaderStream;
+
+use crate::AsyncBufReadSeek;
+
+/// The default size of the buffer the body of a PUT is read with.
+/// 64 KiB
+pub(super) const DEFAULT_READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The first error of the reader of a request body.
+///
+/// reqwest only reports a generic body error when the stream fails,
+/// the original error is kept here to be returned instead.
+#[derive(Debug, Clone, Default)]
+pub(super) struct ReadError(Arc<Mutex<Option<io::Error>>>);
+
+impl ReadError {
+    pub(super) fn take(&self) -> Option<io::Error> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+/// Stream `reader` as a request body, reading `buffer_size` bytes at a time.
+/// The body is only read as fast as the connection sends it.
+pub(super) fn body(
+    reader: impl AsyncRead + Unpin + Send + 'static,
+    buffer_size: usize,
+    error: ReadError,
+) -> reqwest::Body {
+    let reader = TrackedReader {
+        inner: reader,
+        error,
+    };
+    reqwest::Body::wrap_stream(ReaderStream::with_capacity(reader, buffer_size))
+}
+
+struct TrackedReader<R> {
+    inner: R,
+    error: ReadError,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TrackedReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Err(e)) => {
+                let copy = io::Error::new(e.kind(), e.to_string());
+                self.error.0.lock().unwrap().get_or_insert(e);
+                Poll::Ready(Err(copy))
+            }
+            poll => poll,
+        }
+    }
+}
+
+/// A reader shared by the bodies of the requests of a chunked upload.
+/// The requests are sent one after another, each seeks to its chunk first.
+#[derive(Clone)]
+pub(super) struct SharedReader(Arc<Mutex<Box<dyn AsyncBufReadSeek>>>);
+
+impl SharedReader {
+    pub(super) fn new(reader: Box<dyn AsyncBufReadSeek>) -> Self {
+        Self(Arc::new(Mutex::new(reader)))
+    }
+}
+
+impl AsyncRead for SharedReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut **self.0.lock().unwrap()).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for SharedReader {
+    fn start_seek(self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        Pin::new(&mut **self.0.lock().unwrap()).start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Pin::new(&mut **self.0.lock().unwrap()).poll_complete(cx)
+    }
+}
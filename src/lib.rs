@@ -1,8 +1,35 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use async_trait::async_trait;
 
 pub mod backend;
+pub mod path;
+
+/// Makes the names of temporary files unique within the process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// An id unique across processes and uploads, for temporary names.
+pub(crate) fn unique_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    format!(
+        "{}-{:x}-{:x}",
+        std::process::id(),
+        nanos,
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// The lowercase hexadecimal digits of a digest.
+pub(crate) fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
 
 pub trait AsyncBufReadSeek:
     tokio::io::AsyncRead + tokio::io::AsyncSeek + Unpin + Send + Sync
@@ -14,6 +41,48 @@ impl<T> AsyncBufReadSeek for T where
 {
 }
 
+/// Storage space of a backend, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub total: u64,
+    pub used: u64,
+    pub remaining: u64,
+    /// Space used by deleted items, e.g. the recycle bin.
+    /// `None` if the backend doesn't report it.
+    pub deleted: Option<u64>,
+}
+
+/// Attributes of the source file to preserve on the backend.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+    /// Unix permission bits, e.g. `0o644`.
+    pub permissions: Option<u32>,
+    /// Extended attributes by name, e.g. `user.sha256`.
+    pub xattrs: BTreeMap<String, Vec<u8>>,
+}
+
+impl From<&std::fs::Metadata> for Metadata {
+    /// The times and permissions of a source file.
+    fn from(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt as _;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        Self {
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+            permissions,
+            xattrs: BTreeMap::new(),
+        }
+    }
+}
+
 #[async_trait]
 pub trait Backend: Send + Sync {
     async fn upload(
@@ -22,4 +91,41 @@ pub trait Backend: Send + Sync {
         size: u64,
         path: PathBuf,
     ) -> Result<(), Box<dyn snafu::Error>>;
+
+    /// Upload a file and preserve its `metadata` where the backend supports it.
+    /// Backends without support ignore the metadata.
+    async fn upload_with_metadata(
+        &self,
+        reader: Box<dyn AsyncBufReadSeek>,
+        size: u64,
+        path: PathBuf,
+        metadata: Metadata,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        let _ = metadata;
+        self.upload(reader, size, path).await
+    }
+
+    /// Upload the file at `source`, preserving its metadata where the backend supports it.
+    /// Backends may copy it without reading it, e.g. with a reflink.
+    async fn upload_file(
+        &self,
+        source: PathBuf,
+        path: PathBuf,
+    ) -> Result<(), Box<dyn snafu::Error>> {
+        let file = tokio::fs::File::open(&source).await?;
+        let metadata = file.metadata().await?;
+        self.upload_with_metadata(
+            Box::new(file),
+            metadata.len(),
+            path,
+            Metadata::from(&metadata),
+        )
+        .await
+    }
+
+    /// Get the storage space of the backend.
+    /// Returns `None` if the backend can't report it.
+    async fn quota(&self) -> Result<Option<Quota>, Box<dyn snafu::Error>> {
+        Ok(None)
+    }
 }
@@ -0,0 +1,132 @@
+use std::{
+    ffi::OsStr,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use snafu::Snafu;
+
+/// A normalized path below the folder of a backend.
+///
+/// It only consists of normal components, so joining it onto a folder
+/// can't leave that folder. `.` components are dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelativePath(PathBuf);
+
+impl RelativePath {
+    /// Normalize an upload path, rejecting absolute paths, prefixes, `..` and
+    /// paths without a file name.
+    pub fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let invalid = |reason| Error::InvalidPath {
+            path: path.to_string_lossy().to_string(),
+            reason,
+        };
+
+        let mut normalized = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => normalized.push(name),
+                Component::CurDir => {}
+                Component::ParentDir => return Err(invalid(Reason::ParentDir)),
+                Component::RootDir | Component::Prefix(_) => return Err(invalid(Reason::Absolute)),
+            }
+        }
+        if normalized.as_os_str().is_empty() {
+            return Err(invalid(Reason::Empty));
+        }
+        Ok(Self(normalized))
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    /// The name of the file.
+    pub fn file_name(&self) -> &OsStr {
+        // Never empty, see `new`
+        self.0.file_name().unwrap_or_default()
+    }
+
+    /// The names of the folders and the file, from the top.
+    pub fn names(&self) -> impl Iterator<Item = &OsStr> {
+        self.0.iter()
+    }
+
+    /// Join the path onto `folder`.
+    pub fn to_path(&self, folder: impl AsRef<Path>) -> PathBuf {
+        folder.as_ref().join(&self.0)
+    }
+}
+
+impl AsRef<Path> for RelativePath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl fmt::Display for RelativePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.display().fmt(f)
+    }
+}
+
+/// Why a path was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// An absolute path or a Windows prefix like `C:`.
+    Absolute,
+    /// A `..` component.
+    ParentDir,
+    /// No file name.
+    Empty,
+    /// A symlink below the folder points outside of it.
+    SymlinkEscape,
+    /// The path is used by the backend itself, e.g. for previous versions.
+    Reserved,
+    /// The name is kept for checksum files, e.g. it ends with `.sha256`.
+    ChecksumFile,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            Reason::Absolute => "absolute paths are not allowed",
+            Reason::ParentDir => "`..` is not allowed",
+            Reason::Empty => "the path has no file name",
+            Reason::SymlinkEscape => "a symlink leads outside of the folder",
+            Reason::Reserved => "the path is reserved by the backend",
+            Reason::ChecksumFile => "the name is kept for checksum files",
+        };
+        f.write_str(reason)
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid path {}: {}", path, reason))]
+    InvalidPath { path: String, reason: Reason },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        let path = RelativePath::new("./backups/./2026/db.tar").unwrap();
+        assert_eq!(path.as_path(), Path::new("backups/2026/db.tar"));
+        assert_eq!(path.file_name(), "db.tar");
+        assert_eq!(path.names().count(), 3);
+
+        let reason = |path| match RelativePath::new(path) {
+            Err(Error::InvalidPath { reason, .. }) => Some(reason),
+            Ok(_) => None,
+        };
+        assert_eq!(reason("/etc/passwd"), Some(Reason::Absolute));
+        assert_eq!(reason("../../etc/cron.d/x"), Some(Reason::ParentDir));
+        assert_eq!(reason("a/../../b"), Some(Reason::ParentDir));
+        assert_eq!(reason(""), Some(Reason::Empty));
+        assert_eq!(reason("."), Some(Reason::Empty));
+    }
+}